@@ -0,0 +1,330 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::persist::snapshot;
+
+const ENV_PREFIX: &str = "SPACE_GAME_";
+
+/// Every setting can be set in the config file, as environment variable or as CLI flag.
+/// Later ones override earlier ones.
+///
+/// The setting `tick-interval` is `tick-interval` in the file,
+/// `SPACE_GAME_TICK_INTERVAL` as environment variable and `--tick-interval` as CLI flag.
+const KEYS: &[&str] = &[
+    "persist",
+    "sqlite",
+    "journal",
+    "listen",
+    "tick-interval",
+    "log-level",
+    "snapshot-interval",
+    "snapshot-folder",
+    "snapshot-keep-latest",
+    "snapshot-keep-daily",
+    "seed",
+    "spawn-tables",
+    "admin-token",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Root directory of the yaml persist files
+    pub persist: PathBuf,
+    /// Use this `SQLite` database instead of the yaml files
+    pub sqlite: Option<PathBuf>,
+    /// Append-only log of every game state change. Next to the persist when unset.
+    pub journal: Option<PathBuf>,
+    pub listen: String,
+    /// Seconds between two gameloop ticks
+    pub tick_interval: u64,
+    pub log_level: LogLevel,
+    /// Minutes between two snapshots. No snapshots are taken when unset.
+    pub snapshot_interval: Option<u64>,
+    /// Next to the persist when unset
+    pub snapshot_folder: Option<PathBuf>,
+    pub snapshot_keep_latest: usize,
+    pub snapshot_keep_daily: usize,
+    /// Seed of the gameloop randomness when the persist has none yet
+    pub seed: Option<u64>,
+    /// Data file of the site spawn tables. The built-in ones are used when unset.
+    pub spawn_tables: Option<PathBuf>,
+    /// Bearer token the `/admin` routes require. They are refused when unset.
+    pub admin_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            persist: "persist".into(),
+            sqlite: None,
+            journal: None,
+            listen: "[::]:8080".into(), // Works for both IPv4 and IPv6
+            tick_interval: 15,
+            log_level: LogLevel::Info,
+            snapshot_interval: None,
+            snapshot_folder: None,
+            snapshot_keep_latest: 12,
+            snapshot_keep_daily: 7,
+            seed: None,
+            spawn_tables: None,
+            admin_token: None,
+        }
+    }
+}
+
+impl Config {
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+            value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("failed to parse setting {} {}", key, value))
+        }
+
+        match key {
+            "persist" => self.persist = value.into(),
+            "sqlite" => self.sqlite = Some(value.into()),
+            "journal" => self.journal = Some(value.into()),
+            "listen" => self.listen = value.to_string(),
+            "tick-interval" => self.tick_interval = parse(key, value)?,
+            "log-level" => self.log_level = parse(key, value)?,
+            "snapshot-interval" => self.snapshot_interval = Some(parse(key, value)?),
+            "snapshot-folder" => self.snapshot_folder = Some(value.into()),
+            "snapshot-keep-latest" => self.snapshot_keep_latest = parse(key, value)?,
+            "snapshot-keep-daily" => self.snapshot_keep_daily = parse(key, value)?,
+            "seed" => self.seed = Some(parse(key, value)?),
+            "spawn-tables" => self.spawn_tables = Some(value.into()),
+            "admin-token" => self.admin_token = Some(value.to_string()),
+            _ => return Err(anyhow::anyhow!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Separate universes get separate journals without setting them explicitly.
+    pub fn journal(&self) -> PathBuf {
+        self.journal
+            .clone()
+            .unwrap_or_else(|| self.next_to_persist("-journal.jsonl"))
+    }
+
+    /// Sibling of the persist root so it is not part of the persist itself.
+    fn next_to_persist(&self, suffix: &str) -> PathBuf {
+        let mut name = self
+            .persist
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("persist"))
+            .to_os_string();
+        name.push(suffix);
+        self.persist.with_file_name(name)
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(self.tick_interval)
+    }
+
+    pub fn snapshot_settings(&self) -> Option<snapshot::Settings> {
+        self.snapshot_interval.map(|minutes| snapshot::Settings {
+            folder: self
+                .snapshot_folder
+                .clone()
+                .unwrap_or_else(|| self.next_to_persist("-snapshots")),
+            interval: Duration::from_secs(minutes * 60),
+            retention: snapshot::Retention {
+                keep_latest: self.snapshot_keep_latest,
+                keep_daily: self.snapshot_keep_daily,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            _ => Err(anyhow::anyhow!("unknown log level {}", s)),
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether output of the given level should be printed.
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+pub struct Arguments {
+    pub config: Config,
+    /// Snapshot to restore before starting
+    pub restore: Option<PathBuf>,
+    /// Journal to replay into the empty persist instead of starting
+    pub replay: Option<PathBuf>,
+}
+
+/// Load the config from the config file, environment variables and CLI flags.
+///
+/// The config file is given with `--config <file>` or `SPACE_GAME_CONFIG`.
+/// A snapshot is restored with `restore <snapshot>`.
+/// A journal is replayed with `replay <journal>`.
+pub fn load() -> Result<Arguments> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let env = std::env::vars().collect::<Vec<_>>();
+    load_from(&args, &env)
+}
+
+fn load_from(args: &[String], env: &[(String, String)]) -> Result<Arguments> {
+    let mut file = env_value(env, "config").map(PathBuf::from);
+    let mut restore = None;
+    let mut replay = None;
+    let mut flags = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "restore" {
+            let snapshot = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("restore needs a snapshot file"))?;
+            restore = Some(PathBuf::from(snapshot));
+        } else if arg == "replay" {
+            let journal = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("replay needs a journal file"))?;
+            replay = Some(PathBuf::from(journal));
+        } else if let Some(flag) = arg.strip_prefix("--") {
+            let (key, value) = if let Some((key, value)) = flag.split_once('=') {
+                (key, value.to_string())
+            } else {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("flag --{} needs a value", flag))?;
+                (flag, value.clone())
+            };
+            if key == "config" {
+                file = Some(value.into());
+            } else {
+                flags.push((key.to_string(), value));
+            }
+        } else {
+            return Err(anyhow::anyhow!("unknown argument {}", arg));
+        }
+    }
+
+    let mut config = if let Some(file) = file {
+        let content = std::fs::read_to_string(&file)
+            .map_err(|err| anyhow::anyhow!("failed to read config {:?} {}", file, err))?;
+        serde_yaml::from_str(&content)
+            .map_err(|err| anyhow::anyhow!("failed to parse config {:?} {}", file, err))?
+    } else {
+        Config::default()
+    };
+
+    for key in KEYS {
+        if let Some(value) = env_value(env, key) {
+            config.set(key, value)?;
+        }
+    }
+    for (key, value) in flags {
+        config.set(&key, &value)?;
+    }
+
+    Ok(Arguments {
+        config,
+        restore,
+        replay,
+    })
+}
+
+fn env_value<'e>(env: &'e [(String, String)], key: &str) -> Option<&'e str> {
+    let name = format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('-', "_"));
+    env.iter()
+        .find(|(k, _)| k == &name)
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+fn strings(list: &[&str]) -> Vec<String> {
+    list.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn defaults() {
+    let arguments = load_from(&[], &[]).unwrap();
+    assert_eq!(arguments.config.listen, "[::]:8080");
+    assert_eq!(arguments.config.tick_interval(), Duration::from_secs(15));
+    assert!(arguments.config.snapshot_settings().is_none());
+    assert!(arguments.config.admin_token.is_none());
+    assert_eq!(
+        arguments.config.journal(),
+        PathBuf::from("persist-journal.jsonl")
+    );
+    assert!(arguments.restore.is_none());
+    assert!(arguments.replay.is_none());
+}
+
+#[test]
+fn flags_override_env() {
+    let env = vec![
+        ("SPACE_GAME_PERSIST".to_string(), "universe-a".to_string()),
+        ("SPACE_GAME_TICK_INTERVAL".to_string(), "5".to_string()),
+    ];
+    let args = strings(&["--tick-interval", "2", "--listen=[::]:8081"]);
+    let config = load_from(&args, &env).unwrap().config;
+    assert_eq!(config.persist, PathBuf::from("universe-a"));
+    assert_eq!(config.journal(), PathBuf::from("universe-a-journal.jsonl"));
+    assert_eq!(config.tick_interval, 2);
+    assert_eq!(config.listen, "[::]:8081");
+}
+
+#[test]
+fn restore_command() {
+    let args = strings(&[
+        "restore",
+        "snapshots/persist-42.tar.gz",
+        "--log-level",
+        "debug",
+    ]);
+    let arguments = load_from(&args, &[]).unwrap();
+    assert_eq!(
+        arguments.restore,
+        Some(PathBuf::from("snapshots/persist-42.tar.gz"))
+    );
+    assert_eq!(arguments.config.log_level, LogLevel::Debug);
+}
+
+#[test]
+fn unknown_flag_fails() {
+    assert!(load_from(&strings(&["--tick", "2"]), &[]).is_err());
+    assert!(load_from(&strings(&["--tick-interval", "fast"]), &[]).is_err());
+}
+
+#[test]
+fn config_file_keys_match_flags() {
+    let config: Config = serde_yaml::from_str("tick-interval: 3\nsnapshot-interval: 60\n").unwrap();
+    assert_eq!(config.tick_interval, 3);
+    assert_eq!(config.persist, PathBuf::from("persist"));
+    assert_eq!(
+        config.snapshot_settings().unwrap().folder,
+        PathBuf::from("persist-snapshots")
+    );
+}
@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_std::channel::{bounded, Receiver, Sender};
+
+/// Never spin without any pause between ticks
+const MIN_TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Longer ones would only be a worse way to pause the gameloop
+const MAX_TICK_INTERVAL: Duration = Duration::from_secs(86_400);
+
+/// Runtime control over the gameloop shared with the admin endpoints.
+pub struct Control {
+    paused: AtomicBool,
+    tick_interval_millis: AtomicU64,
+    /// Wakes the waiting gameloop when the tick interval changes
+    changed: (Sender<()>, Receiver<()>),
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    pub paused: bool,
+    /// Seconds between two gameloop ticks
+    pub tick_interval: f64,
+}
+
+impl Control {
+    pub fn new(tick_interval: Duration) -> Self {
+        let control = Self {
+            paused: AtomicBool::new(false),
+            tick_interval_millis: AtomicU64::new(0),
+            changed: bounded(1),
+        };
+        control.set_tick_interval(tick_interval);
+        control
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_millis(self.tick_interval_millis.load(Ordering::Relaxed))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn set_tick_interval(&self, tick_interval: Duration) {
+        let millis = tick_interval
+            .clamp(MIN_TICK_INTERVAL, MAX_TICK_INTERVAL)
+            .as_millis() as u64;
+        self.tick_interval_millis.store(millis, Ordering::Relaxed);
+        // A full channel already wakes the gameloop
+        let _ = self.changed.0.try_send(());
+    }
+
+    /// Wait until the tick interval changes.
+    pub async fn tick_interval_changed(&self) {
+        let _ = self.changed.1.recv().await;
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            paused: self.is_paused(),
+            tick_interval: self.tick_interval().as_secs_f64(),
+        }
+    }
+}
+
+#[test]
+fn tick_interval_is_bounded() {
+    let control = Control::new(Duration::from_secs(15));
+    assert_eq!(control.tick_interval(), Duration::from_secs(15));
+    control.set_tick_interval(Duration::ZERO);
+    assert_eq!(control.tick_interval(), MIN_TICK_INTERVAL);
+    control.set_tick_interval(Duration::MAX);
+    assert_eq!(control.tick_interval(), MAX_TICK_INTERVAL);
+}
@@ -3,18 +3,46 @@ use space_game_typings::fixed::item::{Item, Ore};
 use space_game_typings::fixed::npc_faction::NpcFaction;
 use space_game_typings::fixed::solarsystem::Solarsystem;
 use space_game_typings::fixed::Statics;
-use space_game_typings::market::{Order, Trader};
+use space_game_typings::market::{Order, Trade, Trader};
 
-use crate::persist::{Market, Persist};
+use crate::config::{log_enabled, LogLevel};
+use crate::persist::journal::Event;
+use crate::persist::{Journal, Market, Persist};
 
-pub fn all(statics: &Statics, persist: &mut Persist) -> anyhow::Result<()> {
+pub fn all(statics: &Statics, persist: &mut Persist, rng: &mut impl Rng) -> anyhow::Result<()> {
+    let trades = settle(persist);
+    persist.journal.record(Event::Market { trades });
+    persist.transaction(|persist| {
+        generate_ore_orders(statics, &mut persist.market, &mut persist.journal, rng)
+    })
+}
+
+/// Resolve the markets and hand out the goods and paperclips of every trade.
+/// Every item market settles in its own transaction.
+/// A failing one keeps its orders for the next tick without holding up the others.
+pub fn settle(persist: &mut Persist) -> Vec<(Item, Trade)> {
+    let mut trades = Vec::new();
+    for item in persist.market.items() {
+        match persist.transaction(|persist| settle_item(persist, item)) {
+            Ok(settled) => trades.extend(settled.into_iter().map(|trade| (item, trade))),
+            Err(err) => eprintln!("ERROR gameloop::market::settle {:?} {}", item, err),
+        }
+    }
+    trades
+}
+
+fn settle_item(persist: &mut Persist, item: Item) -> anyhow::Result<Vec<Trade>> {
     let assets = &mut persist.player_station_assets;
     let generals = &mut persist.player_generals;
     let market = &mut persist.market;
     let notifications = &mut persist.player_notifications;
+    let tick = persist.clock.read();
 
-    for (item, trade) in market.trade()? {
-        println!("trade happened {:?} {:?}", item, trade);
+    let trades = market.trade(item)?;
+    for trade in trades.iter().copied() {
+        if log_enabled(LogLevel::Info) {
+            println!("trade happened {:?} {:?}", item, trade);
+        }
 
         // Give player the goods
         if let Trader::Player(player) = trade.buyer {
@@ -31,26 +59,32 @@ pub fn all(statics: &Statics, persist: &mut Persist) -> anyhow::Result<()> {
 
         // Notify about trade
         if let Trader::Player(player) = trade.buyer {
-            notifications.add(player, (item, trade))?;
+            notifications.add(player, tick, (item, trade))?;
         }
         if let Trader::Player(player) = trade.seller {
-            notifications.add(player, (item, trade))?;
+            notifications.add(player, tick, (item, trade))?;
         }
     }
 
-    generate_ore_orders(statics, market)?;
-
-    Ok(())
+    Ok(trades)
 }
 
 #[allow(clippy::cast_possible_truncation)]
-fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result<()> {
-    let mut rng = rand::thread_rng();
+fn generate_ore_orders(
+    statics: &Statics,
+    market: &mut Market,
+    journal: &mut Journal,
+    rng: &mut impl Rng,
+) -> anyhow::Result<()> {
     let trader = Trader::Npc(NpcFaction::Guards);
 
+    // HashMap order differs between runs which would mix up the random numbers
+    let mut solarsystems = statics.solarsystems.data.iter().collect::<Vec<_>>();
+    solarsystems.sort_by_key(|(solarsystem, _)| solarsystem.to_string());
+
     let ore = Ore::Aromit;
     let orders = get_npc_buy_orders(market, ore);
-    for (solarsystem, details) in &statics.solarsystems.data {
+    for &(solarsystem, details) in &solarsystems {
         for station in 0..details.stations.len() as u8 {
             let remaining_amount: u32 = orders
                 .iter()
@@ -58,8 +92,10 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
                 .map(|o| o.amount)
                 .sum();
             if remaining_amount < 800 {
-                market.buy(
-                    ore.into(),
+                buy_npc(
+                    market,
+                    journal,
+                    ore,
                     Order::new_now(*solarsystem, station, trader, 1000, 200),
                 )?;
             }
@@ -68,7 +104,7 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
 
     let ore = Ore::Solmit;
     let orders = get_npc_buy_orders(market, ore);
-    for (solarsystem, details) in &statics.solarsystems.data {
+    for &(solarsystem, details) in &solarsystems {
         let remaining_amount: u32 = orders
             .iter()
             .filter(|o| o.solarsystem == *solarsystem)
@@ -77,8 +113,10 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
         if remaining_amount < 500 {
             let stations = details.stations.len() as u8;
             let station = rng.gen_range(0..stations);
-            market.buy(
-                ore.into(),
+            buy_npc(
+                market,
+                journal,
+                ore,
                 Order::new_now(*solarsystem, station, trader, 800, 350),
             )?;
         }
@@ -86,7 +124,7 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
 
     let ore = Ore::Tormit;
     let orders = get_npc_buy_orders(market, ore);
-    for (solarsystem, details) in &statics.solarsystems.data {
+    for &(solarsystem, details) in &solarsystems {
         let remaining_amount: u32 = orders
             .iter()
             .filter(|o| o.solarsystem == *solarsystem)
@@ -95,8 +133,10 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
         if remaining_amount < 300 {
             let stations = details.stations.len() as u8;
             let station = rng.gen_range(0..stations);
-            market.buy(
-                ore.into(),
+            buy_npc(
+                market,
+                journal,
+                ore,
                 Order::new_now(*solarsystem, station, trader, 500, 450),
             )?;
         }
@@ -106,8 +146,10 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
     let orders = get_npc_buy_orders(market, ore);
     let remaining_amount: u32 = orders.iter().map(|o| o.amount).sum();
     if remaining_amount < 100 {
-        market.buy(
-            ore.into(),
+        buy_npc(
+            market,
+            journal,
+            ore,
             Order::new_now(Solarsystem::Vosu, 0, trader, 200, 950),
         )?;
     }
@@ -115,6 +157,18 @@ fn generate_ore_orders(statics: &Statics, market: &mut Market) -> anyhow::Result
     Ok(())
 }
 
+fn buy_npc<I: Into<Item>>(
+    market: &mut Market,
+    journal: &mut Journal,
+    item: I,
+    order: Order,
+) -> anyhow::Result<()> {
+    let item = item.into();
+    market.buy(item, order)?;
+    journal.record(Event::NpcBuyOrder { item, order });
+    Ok(())
+}
+
 fn get_npc_buy_orders<I: Into<Item>>(market: &Market, item: I) -> Vec<Order> {
     market
         .get(item.into())
@@ -1,61 +1,163 @@
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use anyhow::anyhow;
-use async_std::sync::Mutex;
-use async_std::task::{sleep, spawn};
+use async_std::future::timeout;
+use async_std::sync::{Mutex, RwLock};
+use async_std::task::spawn;
 use space_game_typings::fixed::Statics;
 
-use crate::persist::Persist;
+use crate::config::{log_enabled, LogLevel};
+use crate::persist::journal::Event;
+use crate::persist::{snapshot, Persist};
+use crate::spawn_tables::SpawnTables;
 
+mod control;
 mod market;
+mod replay;
 mod site_round;
 mod sites;
 
-pub async fn start(statics: Arc<Statics>, persist: Arc<Mutex<Persist>>) -> anyhow::Result<()> {
+pub use self::control::Control;
+pub use self::replay::replay;
+
+pub async fn start(
+    statics: Arc<Statics>,
+    spawn_tables: Arc<SpawnTables>,
+    persist: Arc<Mutex<Persist>>,
+    view: Arc<RwLock<Persist>>,
+    control: Arc<Control>,
+    snapshots: Option<snapshot::Settings>,
+) -> anyhow::Result<()> {
     let mut persist_once = persist.lock_arc().await;
-    once(&statics, &mut persist_once)?;
+    step(&statics, &spawn_tables, &mut persist_once, &view).await?;
 
-    spawn(async {
-        do_loop(statics, persist).await;
+    spawn(async move {
+        do_loop(statics, spawn_tables, persist, view, control, snapshots).await;
     });
     Ok(())
 }
 
-async fn do_loop(statics: Arc<Statics>, persist: Arc<Mutex<Persist>>) -> ! {
+async fn do_loop(
+    statics: Arc<Statics>,
+    spawn_tables: Arc<SpawnTables>,
+    persist: Arc<Mutex<Persist>>,
+    view: Arc<RwLock<Persist>>,
+    control: Arc<Control>,
+    snapshots: Option<snapshot::Settings>,
+) -> ! {
+    let mut last_snapshot = Instant::now();
+    let mut last_tick = Instant::now();
     loop {
-        sleep(Duration::from_secs(15)).await;
+        let remaining = control.tick_interval().saturating_sub(last_tick.elapsed());
+        if !remaining.is_zero() {
+            // A changed interval might already be due
+            if timeout(remaining, control.tick_interval_changed())
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+        }
+        last_tick = Instant::now();
+        if control.is_paused() {
+            continue;
+        }
+
         let mut persist = persist.lock_arc().await;
-        if let Err(err) = once(&statics, &mut persist) {
+        if let Err(err) = step(&statics, &spawn_tables, &mut persist, &view).await {
             eprintln!("ERROR gameloop {}", err);
         }
+
+        // Still holding the persist lock so the snapshot is consistent
+        if let Some(settings) = &snapshots {
+            if last_snapshot.elapsed() >= settings.interval {
+                last_snapshot = Instant::now();
+                if let Err(err) = create_snapshot(&persist, settings) {
+                    eprintln!("ERROR gameloop::snapshot {}", err);
+                }
+            }
+        }
+    }
+}
+
+fn create_snapshot(persist: &Persist, settings: &snapshot::Settings) -> anyhow::Result<()> {
+    let measure = Instant::now();
+    let file = persist.create_snapshot(&settings.folder)?;
+    let pruned = snapshot::prune(&settings.folder, settings.retention)?;
+    if log_enabled(LogLevel::Info) {
+        println!(
+            "gameloop::snapshot {:?} pruned:{} took:{:?}",
+            file,
+            pruned.len(),
+            measure.elapsed()
+        );
     }
+    Ok(())
 }
 
 // TODO: ensure players in warp warp to existing site
 
-fn once(statics: &Statics, persist: &mut Persist) -> anyhow::Result<()> {
+/// Run exactly one tick right now.
+/// Also used to step through the game by hand while the gameloop is paused.
+///
+/// Readers of the published view keep seeing the state before the tick until it is done.
+pub async fn step(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    view: &RwLock<Persist>,
+) -> anyhow::Result<()> {
+    persist.stage()?;
+    let result = once(statics, spawn_tables, persist);
+    let _view = view.write().await;
+    persist.publish();
+    result
+}
+
+fn once(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+) -> anyhow::Result<()> {
+    let tick = persist.clock.advance()?;
+    persist.journal.record(Event::Tick);
+    let mut rng = persist.random.next_rng()?;
+
     let site_round_took = {
         let measure = Instant::now();
-        site_round::all(statics, persist);
+        site_round::all(statics, spawn_tables, persist, &mut rng)
+            .map_err(|err| anyhow!("gameloop::site_round {}", err))?;
         measure.elapsed()
     };
 
     let sites_took = {
         let measure = Instant::now();
-        sites::all(statics, persist).map_err(|err| anyhow!("gameloop::sites {}", err))?;
+        sites::all(statics, spawn_tables, persist, &mut rng)
+            .map_err(|err| anyhow!("gameloop::sites {}", err))?;
         measure.elapsed()
     };
 
     let market_took = {
         let measure = Instant::now();
-        market::all(statics, persist).map_err(|err| anyhow!("gameloop::market {}", err))?;
+        market::all(statics, persist, &mut rng)
+            .map_err(|err| anyhow!("gameloop::market {}", err))?;
         measure.elapsed()
     };
 
-    println!(
-        "gameloop::once site_round:{:?} site:{:?} market:{:?}",
-        site_round_took, sites_took, market_took
-    );
+    let flush_took = {
+        let measure = Instant::now();
+        persist
+            .flush()
+            .map_err(|err| anyhow!("gameloop::flush {}", err))?;
+        measure.elapsed()
+    };
+
+    if log_enabled(LogLevel::Info) {
+        println!(
+            "gameloop::once {} site_round:{:?} site:{:?} market:{:?} flush:{:?}",
+            tick, site_round_took, sites_took, market_took, flush_took
+        );
+    }
     Ok(())
 }
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use space_game_typings::fixed::Statics;
+
+use super::{market, site_round};
+use crate::persist::journal::Event;
+use crate::persist::{ensure_static_sites, Persist};
+use crate::spawn_tables::SpawnTables;
+use crate::station;
+
+/// Rebuild the persist from the journal.
+/// The persist should be empty and must not record a journal itself.
+///
+/// Only works as long as the site rounds of the typings are deterministic.
+/// The given spawn tables are used until the journal recorded others.
+pub fn replay(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    events: Vec<Event>,
+) -> anyhow::Result<()> {
+    let mut spawn_tables = spawn_tables.clone();
+    ensure_static_sites(statics, &spawn_tables, &mut persist.sites)?;
+    let mut ticks = 0_usize;
+    // The site rounds of a tick were advanced together so they have to be replayed together
+    let mut rounds = Vec::new();
+    for (index, event) in events.into_iter().enumerate() {
+        if let Event::SiteRound {
+            solarsystem,
+            site,
+            instructions,
+            seed,
+        } = event
+        {
+            rounds.push(site_round::Round {
+                solarsystem,
+                site,
+                instructions: instructions.into_iter().collect::<HashMap<_, _>>(),
+                seed,
+            });
+            continue;
+        }
+
+        run_site_rounds(statics, &spawn_tables, persist, std::mem::take(&mut rounds)).map_err(
+            |err| {
+                anyhow!(
+                    "replay journal site rounds before event {} failed {}",
+                    index + 1,
+                    err
+                )
+            },
+        )?;
+        if matches!(event, Event::Tick) {
+            ticks += 1;
+        }
+        if let Event::SpawnTables { tables } = event {
+            tables.validate(statics).map_err(|err| {
+                anyhow!(
+                    "replay journal event {} recorded invalid spawn tables {}",
+                    index + 1,
+                    err
+                )
+            })?;
+            spawn_tables = tables;
+            // The original run ensured the static sites right after recording them
+            ensure_static_sites(statics, &spawn_tables, &mut persist.sites)?;
+            continue;
+        }
+        apply(statics, persist, event)
+            .map_err(|err| anyhow!("replay journal event {} failed {}", index + 1, err))?;
+    }
+    run_site_rounds(statics, &spawn_tables, persist, rounds)?;
+    persist.flush()?;
+    println!("  replayed {} ticks", ticks);
+    Ok(())
+}
+
+fn run_site_rounds(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    rounds: Vec<site_round::Round>,
+) -> anyhow::Result<()> {
+    if rounds.is_empty() {
+        return Ok(());
+    }
+    // Sites failing in the original run were not recorded
+    if let Some((solarsystem, site, err)) =
+        site_round::run(statics, spawn_tables, persist, rounds)?.pop()
+    {
+        return Err(anyhow!(
+            "site round {:?} {:?} failed {}",
+            solarsystem,
+            site,
+            err
+        ));
+    }
+    Ok(())
+}
+
+fn apply(statics: &Statics, persist: &mut Persist, event: Event) -> anyhow::Result<()> {
+    match event {
+        Event::Tick => {
+            persist.clock.advance()?;
+        }
+        Event::SiteRound { .. } => unreachable!("site rounds are replayed together"),
+        Event::SpawnTables { .. } => unreachable!("spawn tables are switched by the replay"),
+        Event::StationInstructions {
+            player,
+            instructions,
+        } => {
+            station::do_instructions(statics, persist, player, &instructions)?;
+        }
+        Event::SiteAdded {
+            solarsystem,
+            planet,
+            site,
+            entities,
+        } => {
+            persist
+                .sites
+                .add_site(solarsystem, planet, site, &entities)?;
+        }
+        Event::EntitiesSpawned {
+            solarsystem,
+            site,
+            mut entities,
+        } => {
+            let mut all = persist.sites.read_entities(solarsystem, site)?;
+            all.append(&mut entities);
+            persist.sites.write_entities(solarsystem, site, &all)?;
+        }
+        Event::NpcBuyOrder { item, order } => {
+            persist.market.buy(item, order)?;
+        }
+        Event::SiteQuarantined {
+            solarsystem,
+            site,
+            reason,
+        } => {
+            persist.transaction(|persist| {
+                site_round::quarantine(persist, solarsystem, site, reason)
+            })?;
+        }
+        Event::Market { trades } => {
+            let replayed = market::settle(persist);
+            if replayed.len() != trades.len() {
+                eprintln!(
+                    "    replay resulted in {} trades but the journal recorded {}",
+                    replayed.len(),
+                    trades.len()
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,68 +1,600 @@
 use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
+use anyhow::anyhow;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use space_game_typings::fixed::npc_faction::NpcFaction;
 use space_game_typings::fixed::solarsystem::Solarsystem;
 use space_game_typings::fixed::Statics;
 use space_game_typings::player::location::{
     PlayerLocation, PlayerLocationSite, PlayerLocationStation, PlayerLocationWarp,
 };
-use space_game_typings::site::instruction::Instruction;
-use space_game_typings::site::{advance, Entity, Log, Site};
+use space_game_typings::player::Player;
+use space_game_typings::ship::Ship;
+use space_game_typings::site::instruction::Instruction as TypingsInstruction;
+use space_game_typings::site::{advance, Entity, Log, Output, Site, SiteLogActor};
 
-use crate::persist::site::{add_entity_warping, pop_entity_warping};
-use crate::persist::Persist;
+use crate::config::{log_enabled, LogLevel};
+use crate::persist::journal::Event;
+use crate::persist::site::{PirateActivity, QuarantinedSite, Warping, Wreck};
+use crate::persist::{KillRight, Persist};
+use crate::site::{self, BackendInstruction, Instruction, NpcInstruction, WRECK_EXPIRE_TICKS};
+use crate::spawn_tables::SpawnTables;
+use crate::station;
 
 mod npc_instructions;
 
-pub fn all(statics: &Statics, persist: &mut Persist) {
-    for (solarsystem, site) in persist.sites.read_sites_everywhere(&statics.solarsystems) {
-        handle(statics, persist, solarsystem, site).unwrap_or_else(|err| {
-            panic!(
-                "gameloop::site::handle {:?} {:?} {}",
-                solarsystem, site, err
-            );
-        });
+/// Ticks an undocked ship can not be targeted unless its player acts.
+const UNDOCK_INVULNERABLE_TICKS: u64 = 3;
+
+/// Security status lost for every round attacking others without a reason at a guarded site.
+const AGGRESSION_SECURITY_PENALTY: i32 = 2;
+/// Guards attack criminals and players with a security status below this.
+const OUTLAW_SECURITY: i32 = -5;
+const MIN_SECURITY: i32 = -100;
+
+/// Ticks a player stays criminal after attacking someone at a guarded site.
+const CRIMINAL_TICKS: u64 = 15;
+/// Ticks a player stays suspect after attacking someone at an unguarded site.
+const SUSPECT_TICKS: u64 = 10;
+/// Ticks a victim can strike back without being flagged.
+const KILL_RIGHT_TICKS: u64 = 100;
+
+/// Rounds pirates stay in an asteroid field without players around.
+const PIRATE_DESPAWN_IDLE_ROUNDS: u64 = 20;
+
+/// Rounds a mined out asteroid field stays before it is retired.
+const BELT_RETIRE_ROUNDS: u64 = 10;
+/// Partly mined asteroids regrow a bit every this many ticks.
+const ASTEROID_REGROW_INTERVAL: u64 = 10;
+
+/// Instructions of every entity within a site for one round.
+pub struct Round {
+    pub solarsystem: Solarsystem,
+    pub site: Site,
+    pub instructions: HashMap<usize, Vec<Instruction>>,
+    /// Seed of the randomness within the round like the content of wrecks
+    pub seed: u64,
+}
+
+type Failed = (Solarsystem, Site, anyhow::Error);
+
+/// Handle every site on its own.
+/// A site failing to be handled is quarantined so the others keep going.
+pub fn all(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    rng: &mut impl Rng,
+) -> anyhow::Result<()> {
+    let mut sites = persist.sites.read_sites_everywhere(&statics.solarsystems);
+    // HashMap order differs between runs and the seeds are handed out in this order
+    sites.sort_by_cached_key(|(solarsystem, site)| (solarsystem.to_string(), site.to_string()));
+    let collected = {
+        let persist: &Persist = persist;
+        sites
+            .into_par_iter()
+            .map(|(solarsystem, site)| {
+                guarded(|| collect_instructions(statics, persist, solarsystem, site))
+                    .map(|instructions| Round {
+                        solarsystem,
+                        site,
+                        instructions,
+                        seed: 0,
+                    })
+                    .map_err(|err| (solarsystem, site, err))
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut rounds = Vec::new();
+    let mut failed = Vec::new();
+    for result in collected {
+        match result {
+            Ok(mut round) => {
+                round.seed = rng.gen();
+                rounds.push(round);
+            }
+            Err(failure) => failed.push(failure),
+        }
+    }
+    failed.append(&mut run(statics, spawn_tables, persist, rounds)?);
+    for (solarsystem, site, err) in failed {
+        eprintln!(
+            "ERROR gameloop::site::handle {:?} {:?} {}",
+            solarsystem, site, err
+        );
+        let reason = err.to_string();
+        persist
+            .transaction(|persist| {
+                persist.journal.record(Event::SiteQuarantined {
+                    solarsystem,
+                    site,
+                    reason: reason.clone(),
+                });
+                quarantine(persist, solarsystem, site, reason)
+            })
+            .unwrap_or_else(|err| {
+                eprintln!(
+                    "ERROR gameloop::site::quarantine {:?} {:?} {}",
+                    solarsystem, site, err
+                );
+            });
     }
+    Ok(())
 }
 
-#[allow(clippy::too_many_lines)]
-fn handle(
+/// Advance all the rounds and write their results.
+/// Returns the sites which failed and should be quarantined.
+///
+/// Sites are only read while advancing so every site is advanced in parallel.
+/// Their results are written one after another afterwards, each in its own transaction
+/// together with the entities warping out of it.
+pub fn run(
     statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    rounds: Vec<Round>,
+) -> anyhow::Result<Vec<Failed>> {
+    let advanced = {
+        let persist: &Persist = persist;
+        rounds
+            .into_par_iter()
+            .map(|round| {
+                let output = guarded(|| advance_round(statics, persist, &round));
+                (round, output)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut failed = Vec::new();
+    for (round, output) in advanced {
+        let result = output.and_then(|output| {
+            persist.transaction(|persist| {
+                guarded(|| {
+                    persist.journal.record(Event::SiteRound {
+                        solarsystem: round.solarsystem,
+                        site: round.site,
+                        instructions: sorted(&round.instructions),
+                        seed: round.seed,
+                    });
+                    write_output(statics, spawn_tables, persist, &round, output)
+                })
+            })
+        });
+        if let Err(err) = result {
+            failed.push((round.solarsystem, round.site, err));
+        }
+    }
+
+    // HashMap order differs between runs and replays
+    let mut solarsystems = statics
+        .solarsystems
+        .data
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    solarsystems.sort_by_key(ToString::to_string);
+    for solarsystem in solarsystems {
+        // Unreadable sites would make every warp look orphaned
+        let existing = match persist.sites.read_sites(solarsystem) {
+            Ok(sites) => sites.all(),
+            Err(err) => {
+                eprintln!("ERROR gameloop::site_round {} {}", solarsystem, err);
+                continue;
+            }
+        };
+        persist.transaction(|persist| land_orphans(persist, solarsystem, &existing))?;
+    }
+
+    Ok(failed)
+}
+
+/// Sites can disappear while entities are still warping towards them.
+/// Players are brought home with their ship, NPCs are dropped.
+fn land_orphans(
     persist: &mut Persist,
     solarsystem: Solarsystem,
-    site: Site,
+    existing: &[Site],
 ) -> anyhow::Result<()> {
-    let output = {
-        let site_entities = persist.sites.read_entities(solarsystem, site).unwrap();
+    let tick = persist.clock.read();
+    for entity in persist.sites.pop_entity_orphaned(solarsystem, existing)? {
+        if let Entity::Player((player, ship)) = entity {
+            let location = bring_home(persist, player, Some(ship))?;
+            persist.player_notifications.add_message(
+                player,
+                tick,
+                format!(
+                    "The site you were warping to in {} is gone. You ended up in {}.",
+                    solarsystem,
+                    describe(location)
+                ),
+            )?;
+        }
+    }
+    Ok(())
+}
 
-        let mut instructions: HashMap<usize, Vec<Instruction>> = HashMap::new();
+/// Run the action and turn a panic into an error.
+fn guarded<T, F>(action: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T>,
+{
+    catch_unwind(AssertUnwindSafe(action))
+        .unwrap_or_else(|panic| Err(anyhow!("panicked {}", panic_message(&*panic))))
+}
 
-        for (index, entity) in site_entities.iter().enumerate() {
-            if let Entity::Player((player, _)) = entity {
-                let mut additionals = persist.player_site_instructions.read(*player);
-                let all = instructions.entry(index).or_default();
-                all.append(&mut additionals);
+/// Bring the player home with the ship waiting there
+/// or a rookie ship taken from the stored ships of the station.
+fn respawn(persist: &mut Persist, player: Player) -> anyhow::Result<PlayerLocation> {
+    let location = persist.player_generals.read_home_location(player);
+    if let PlayerLocation::Station(station) = location {
+        let mut assets =
+            persist
+                .player_station_assets
+                .read(player, station.solarsystem, station.station);
+        if assets.current_ship.is_none() {
+            let rookie = Ship::default().fitting;
+            if let Some(index) = assets.ships.iter().position(|o| o.fitting == rookie) {
+                assets.current_ship = Some(assets.ships.remove(index));
             }
         }
+        persist.player_station_assets.write(
+            player,
+            station.solarsystem,
+            station.station,
+            &assets,
+        )?;
+    }
+    persist.player_locations.write(player, location)?;
+    Ok(location)
+}
+
+/// Bring the player to the home station keeping the ship.
+fn bring_home(
+    persist: &mut Persist,
+    player: Player,
+    ship: Option<Ship>,
+) -> anyhow::Result<PlayerLocation> {
+    persist.player_site_instructions.write(player, &[])?;
+    let location = persist.player_generals.read_home_location(player);
+    if let (PlayerLocation::Station(station), Some(ship)) = (&location, ship) {
+        let mut assets =
+            persist
+                .player_station_assets
+                .read(player, station.solarsystem, station.station);
+        if assets.current_ship.is_none() {
+            persist
+                .player_insurances
+                .dock(player, station.solarsystem, station.station, None)?;
+            assets.current_ship = Some(ship);
+        } else {
+            persist.player_insurances.dock(
+                player,
+                station.solarsystem,
+                station.station,
+                Some(&ship),
+            )?;
+            assets.ships.push(ship);
+        }
+        persist.player_station_assets.write(
+            player,
+            station.solarsystem,
+            station.station,
+            &assets,
+        )?;
+    }
+    persist.player_locations.write(player, location)?;
+    Ok(location)
+}
+
+fn describe(location: PlayerLocation) -> String {
+    match location {
+        PlayerLocation::Station(o) => {
+            format!("{:?} in {}", Site::Station(o.station), o.solarsystem)
+        }
+        PlayerLocation::Site(o) => format!("{:?} in {}", o.site, o.solarsystem),
+        PlayerLocation::Warp(o) => format!("warp towards {:?} in {}", o.towards, o.solarsystem),
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "without message"
+    }
+}
+
+/// Take the site out of the game and bring the players in it to safety.
+pub fn quarantine(
+    persist: &mut Persist,
+    solarsystem: Solarsystem,
+    site: Site,
+    reason: String,
+) -> anyhow::Result<()> {
+    let tick = persist.clock.read();
+
+    let undocking = if let Site::Station(station) = site {
+        persist.sites.pop_entity_undocking(solarsystem, station)?
+    } else {
+        Vec::new()
+    };
+
+    // The entities might be what is broken
+    let mut players = persist
+        .sites
+        .read_entities(solarsystem, site)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(
+            persist
+                .sites
+                .pop_entity_arrived(solarsystem, site, u64::MAX)?,
+        )
+        .chain(undocking)
+        .filter_map(|entity| match entity {
+            Entity::Player((player, ship)) => Some((player, Some(ship))),
+            _ => None,
+        })
+        .collect::<Vec<(Player, Option<Ship>)>>();
+    for (player, location) in persist.player_locations.read_all() {
+        let affected = match location {
+            PlayerLocation::Site(o) => o.solarsystem == solarsystem && o.site == site,
+            PlayerLocation::Warp(o) => o.solarsystem == solarsystem && o.towards == site,
+            PlayerLocation::Station(_) => false,
+        };
+        if affected && !players.iter().any(|(p, _)| p == &player) {
+            players.push((player, None));
+        }
+    }
 
-        for (index, mut additionals) in npc_instructions::generate(site, &site_entities) {
+    persist.sites.quarantine_site(QuarantinedSite {
+        solarsystem,
+        site,
+        tick,
+        reason,
+    })?;
+
+    for (player, ship) in players {
+        bring_home(persist, player, ship)?;
+        persist.player_notifications.add_message(
+            player,
+            tick,
+            format!(
+                "The site {:?} in {} broke and was closed. You were brought to safety.",
+                site, solarsystem
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn collect_instructions(
+    statics: &Statics,
+    persist: &Persist,
+    solarsystem: Solarsystem,
+    site: Site,
+) -> anyhow::Result<HashMap<usize, Vec<Instruction>>> {
+    let site_entities = persist.sites.read_entities(solarsystem, site)?;
+
+    let mut instructions: HashMap<usize, Vec<Instruction>> = HashMap::new();
+
+    for (index, entity) in site_entities.iter().enumerate() {
+        if let Entity::Player((player, _)) = entity {
+            let mut additionals = persist.player_site_instructions.read(*player);
             let all = instructions.entry(index).or_default();
             all.append(&mut additionals);
         }
+    }
 
-        let mut output = advance(statics, solarsystem, site, &site_entities, &instructions);
+    let tick = persist.clock.read();
+    let outlaws = site_entities
+        .iter()
+        .filter_map(|entity| match entity {
+            Entity::Player((player, _))
+                if persist.player_generals.read_security(*player, tick) < OUTLAW_SECURITY
+                    || persist.player_flags.read(*player).is_criminal(tick) =>
+            {
+                Some(*player)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // NPCs can not dock so they flee into other asteroid fields.
+    // Stargates are guarded which would be no escape for pirates.
+    let escapes = persist
+        .sites
+        .read_sites(solarsystem)?
+        .all()
+        .into_iter()
+        .filter(|o| matches!(o, Site::AsteroidField(_)) && *o != site)
+        .collect::<Vec<_>>();
+    let context = npc_instructions::Context {
+        statics,
+        site_entities: &site_entities,
+        outlaws: &outlaws,
+        escapes: &escapes,
+    };
+    for (index, mut additionals) in npc_instructions::generate(&context) {
+        let all = instructions.entry(index).or_default();
+        all.append(&mut additionals);
+    }
 
-        let mut warping_in = pop_entity_warping(solarsystem, site)?;
-        for entity in &warping_in {
-            output.log.push(Log::WarpIn(entity.into()));
+    Ok(instructions)
+}
+
+fn sorted(instructions: &HashMap<usize, Vec<Instruction>>) -> Vec<(usize, Vec<Instruction>)> {
+    let mut sorted = instructions
+        .iter()
+        .map(|(index, instructions)| (*index, instructions.clone()))
+        .collect::<Vec<_>>();
+    sorted.sort_by_key(|(index, _)| *index);
+    sorted
+}
+
+/// Advance the site with the given instructions of its entities.
+/// Only reads from the persist.
+/// Also returns the entities destroyed within the round.
+fn advance_round(
+    statics: &Statics,
+    persist: &Persist,
+    round: &Round,
+) -> anyhow::Result<(Output, Vec<Entity>)> {
+    let tick = persist.clock.read();
+    let site_entities = persist.sites.read_entities(round.solarsystem, round.site)?;
+    let instructions = protect_invulnerable(persist, tick, &site_entities, &round.instructions)
+        .iter()
+        .map(|(index, instructions)| (*index, site::typings(instructions)))
+        .collect();
+
+    let mut output = advance(
+        statics,
+        round.solarsystem,
+        round.site,
+        &site_entities,
+        &instructions,
+    );
+    let warped = output
+        .warping_out
+        .iter()
+        .map(|(_, _, entity)| entity.clone())
+        .collect::<Vec<_>>();
+    let destroyed = destroyed(
+        &site_entities,
+        &instructions,
+        &output.remaining,
+        &warped,
+        &output.dead,
+    );
+
+    let mut warping_in = persist
+        .sites
+        .read_entity_arrived(round.solarsystem, round.site, tick);
+    for entity in &warping_in {
+        output.log.push(Log::WarpIn(entity.into()));
+    }
+    output.remaining.append(&mut warping_in);
+
+    if let Site::Station(station) = round.site {
+        let mut undocking = persist
+            .sites
+            .read_entity_undocking(round.solarsystem, station);
+        for entity in &undocking {
+            output.log.push(Log::Undock(entity.into()));
         }
-        output.remaining.append(&mut warping_in);
+        output.remaining.append(&mut undocking);
+    }
 
-        output
-    };
+    Ok((output, destroyed))
+}
+
+fn acted(instructions: &HashMap<usize, Vec<Instruction>>, index: usize) -> bool {
+    matches!(instructions.get(&index), Some(own) if !own.is_empty())
+}
+
+/// Drop the shots at invulnerable players.
+/// Players acting on their own are not protected anymore.
+fn protect_invulnerable(
+    persist: &Persist,
+    tick: u64,
+    site_entities: &[Entity],
+    instructions: &HashMap<usize, Vec<Instruction>>,
+) -> HashMap<usize, Vec<Instruction>> {
+    let protected = site_entities
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entity)| match entity {
+            Entity::Player((player, _))
+                if !acted(instructions, index)
+                    && persist
+                        .player_invulnerability
+                        .is_invulnerable(*player, tick) =>
+            {
+                Some(index)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    instructions
+        .iter()
+        .map(|(index, instructions)| {
+            let allowed = instructions
+                .iter()
+                .filter(|o| {
+                    !matches!(o, Instruction::Typings(TypingsInstruction::ModuleTargeted(m)) if protected.contains(&usize::from(m.target_index_in_site)))
+                })
+                .copied()
+                .collect();
+            (*index, allowed)
+        })
+        .collect()
+}
+
+/// Write the results of advancing the site.
+#[allow(clippy::too_many_lines)]
+fn write_output(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    round: &Round,
+    (mut output, destroyed): (Output, Vec<Entity>),
+) -> anyhow::Result<()> {
+    let solarsystem = round.solarsystem;
+    let site = round.site;
+    let tick = persist.clock.read();
+
+    // Still the entities from before the round
+    let before = persist.sites.read_entities(solarsystem, site)?;
+    for (index, entity) in before.iter().enumerate() {
+        if let Entity::Player((player, _)) = entity {
+            let protected = persist.player_invulnerability.read(*player).is_some();
+            let ends = acted(&round.instructions, index)
+                || !persist
+                    .player_invulnerability
+                    .is_invulnerable(*player, tick);
+            if protected && ends {
+                persist.player_invulnerability.write(*player, None)?;
+            }
+        }
+    }
+
+    let attacks = attacks(round, &before);
+    let guarded = is_guarded(&before);
+    let aggressors = flag_aggression(persist, tick, guarded, &before, &attacks)?;
+    if guarded {
+        for player in aggressors {
+            let security = persist.player_generals.read_security(player, tick);
+            let lowered = security
+                .saturating_sub(AGGRESSION_SECURITY_PENALTY)
+                .max(MIN_SECURITY);
+            persist
+                .player_generals
+                .write_security(player, tick, lowered)?;
+        }
+    }
+    reinforce(statics, round, &before, &mut output);
+
+    // Already part of the remaining entities
+    persist.sites.pop_entity_arrived(solarsystem, site, tick)?;
+    if let Site::Station(station) = site {
+        for entity in persist.sites.pop_entity_undocking(solarsystem, station)? {
+            if let Entity::Player((player, _)) = entity {
+                persist
+                    .player_invulnerability
+                    .write(player, Some(tick + UNDOCK_INVULNERABLE_TICKS))?;
+            }
+        }
+    }
 
-    if !output.log.is_empty() {
+    if !output.log.is_empty() && log_enabled(LogLevel::Info) {
         println!(
-            "site_log {:>15} {:?} {} {:?}",
+            "site_log {:>6} {:>15} {:?} {} {:?}",
+            tick,
             solarsystem.to_string(),
             site,
             output.log.len(),
@@ -70,22 +602,59 @@ fn handle(
         );
     }
 
+    let mut wrecks = persist.sites.read_wrecks(solarsystem, site);
+    loot(statics, round, &before, &mut output.remaining, &mut wrecks);
+    wrecks.retain(|o| tick <= o.expires && !o.storage.to_vec().is_empty());
+    let mut rng = StdRng::seed_from_u64(round.seed);
+    for entity in &destroyed {
+        if let Entity::Npc((_, ship)) | Entity::Player((_, ship)) = entity {
+            let actor = SiteLogActor::from(entity);
+            if let Some(wreck) = site::wreck(&mut rng, actor, ship, tick + WRECK_EXPIRE_TICKS) {
+                wrecks.push(wreck);
+            }
+        }
+    }
+    persist.sites.write_wrecks(solarsystem, site, &wrecks)?;
+
+    if let Site::AsteroidField(_) = site {
+        track_pirates(persist, solarsystem, site, &destroyed, &mut output)?;
+        age_asteroid_field(
+            statics,
+            spawn_tables,
+            persist,
+            solarsystem,
+            site,
+            tick,
+            &mut output,
+        )?;
+    }
+
     for player in output.dead {
         persist
             .player_notifications
-            .add(player, output.log.clone())?;
+            .add(player, tick, output.log.clone())?;
         persist.player_site_instructions.write(player, &[])?;
-        // TODO: home station
-        persist
-            .player_locations
-            .write(player, PlayerLocation::default())?;
+        let location = respawn(persist, player)?;
+        persist.player_notifications.add_message(
+            player,
+            tick,
+            format!(
+                "Your ship was destroyed. You woke up in {}.",
+                describe(location)
+            ),
+        )?;
+        let ship = destroyed.iter().find_map(|entity| match entity {
+            Entity::Player((p, ship)) if p == &player => Some(ship),
+            _ => None,
+        });
+        claim_insurance(statics, persist, tick, player, ship)?;
     }
 
     for (solarsystem, station, entity) in output.docking {
         if let Entity::Player((player, ship)) = entity {
             persist
                 .player_notifications
-                .add(player, output.log.clone())?;
+                .add(player, tick, output.log.clone())?;
             persist.player_site_instructions.write(player, &[])?;
             persist.player_locations.write(
                 player,
@@ -99,35 +668,36 @@ fn handle(
                 .player_station_assets
                 .read(player, solarsystem, station);
             assets.current_ship = Some(ship);
+            persist
+                .player_insurances
+                .dock(player, solarsystem, station, None)?;
             persist
                 .player_station_assets
                 .write(player, solarsystem, station, &assets)?;
         }
     }
 
-    for (solarsystem, site, entity) in output.warping_out {
+    for (solarsystem, site, entity) in &output.warping_out {
         if let Entity::Player((player, _)) = entity {
             persist
                 .player_notifications
-                .add(player, output.log.clone())?;
-            persist.player_site_instructions.write(player, &[])?;
+                .add(*player, tick, output.log.clone())?;
+            persist.player_site_instructions.write(*player, &[])?;
             persist.player_locations.write(
-                player,
+                *player,
                 PlayerLocation::Warp(PlayerLocationWarp {
-                    solarsystem,
-                    towards: site,
+                    solarsystem: *solarsystem,
+                    towards: *site,
                 }),
             )?;
         }
-
-        add_entity_warping(solarsystem, site, entity)?;
     }
 
     for entity in &output.remaining {
         if let Entity::Player((player, _)) = entity {
             persist
                 .player_notifications
-                .add(*player, output.log.clone())?;
+                .add(*player, tick, output.log.clone())?;
             persist.player_site_instructions.write(*player, &[])?;
             persist.player_locations.write(
                 *player,
@@ -136,6 +706,20 @@ fn handle(
         }
     }
 
+    // Warps take at least a tick so other sites of this tick do not see them arrive
+    for (target_solarsystem, target, entity) in output.warping_out {
+        let ticks = warp_ticks(persist, solarsystem, site, target_solarsystem, target);
+        let warping = Warping {
+            towards: target,
+            entity,
+            departure: tick,
+            arrival: tick + ticks,
+        };
+        persist
+            .sites
+            .add_entity_warping(target_solarsystem, warping)?;
+    }
+
     if output.remaining.is_empty() {
         persist.sites.remove_site(solarsystem, site)?;
     } else {
@@ -146,3 +730,503 @@ fn handle(
 
     Ok(())
 }
+
+/// Partly mined asteroids regrow slowly up to the biggest ones of the spawn table.
+/// A mined out asteroid field is retired after a cooldown once no player is left in it
+/// and nothing is warping towards it anymore.
+fn age_asteroid_field(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    solarsystem: Solarsystem,
+    site: Site,
+    tick: u64,
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    if tick.checked_rem(ASTEROID_REGROW_INTERVAL) == Some(0) {
+        let table = spawn_tables.get(statics, solarsystem);
+        for entity in &mut output.remaining {
+            if let Entity::Asteroid(asteroid) = entity {
+                if let Some(Entity::Asteroid(cap)) = table.asteroid_cap(asteroid.ore) {
+                    asteroid.remaining_ore = asteroid
+                        .remaining_ore
+                        .saturating_add(1)
+                        .min(cap.remaining_ore.max(asteroid.remaining_ore));
+                    asteroid.remaining_structure = asteroid
+                        .remaining_structure
+                        .saturating_add(1)
+                        .min(cap.remaining_structure.max(asteroid.remaining_structure));
+                }
+            }
+        }
+    }
+
+    let asteroids = output
+        .remaining
+        .iter()
+        .any(|o| matches!(o, Entity::Asteroid(_)));
+    let players = output
+        .remaining
+        .iter()
+        .any(|o| matches!(o, Entity::Player(_)));
+    let arriving = persist
+        .sites
+        .read_entitiy_warping(solarsystem)
+        .iter()
+        .any(|o| o.towards == site);
+    let depleted = if asteroids {
+        0
+    } else {
+        persist.sites.read_depleted(solarsystem, site) + 1
+    };
+    if depleted > BELT_RETIRE_ROUNDS && !players && !arriving {
+        // Without entities the site is removed
+        output.remaining.clear();
+        return Ok(());
+    }
+    persist.sites.write_depleted(solarsystem, site, depleted)
+}
+
+/// Escalate the next wave when players cleared the pirates of an asteroid field.
+/// Pirates without players around leave after a while and the waves start over.
+fn track_pirates(
+    persist: &mut Persist,
+    solarsystem: Solarsystem,
+    site: Site,
+    destroyed: &[Entity],
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    let is_pirate = |o: &Entity| matches!(o, Entity::Npc((NpcFaction::Pirates, _)));
+    let mut activity = persist.sites.read_pirates(solarsystem, site);
+    let players = output
+        .remaining
+        .iter()
+        .any(|o| matches!(o, Entity::Player(_)));
+    let pirates = output.remaining.iter().filter(|o| is_pirate(o)).count();
+
+    if pirates == 0 && destroyed.iter().any(is_pirate) {
+        activity.wave = activity.wave.saturating_add(1);
+    }
+    if players || pirates == 0 {
+        activity.idle = 0;
+    } else {
+        activity.idle += 1;
+    }
+    if activity.idle > PIRATE_DESPAWN_IDLE_ROUNDS {
+        for pirate in output.remaining.iter().filter(|o| is_pirate(o)) {
+            output.log.push(Log::WarpOut(pirate.into()));
+        }
+        output.remaining.retain(|o| !is_pirate(o));
+        activity = PirateActivity::default();
+    }
+
+    persist.sites.write_pirates(solarsystem, site, &activity)
+}
+
+/// The first NPC calling for reinforcements gets a fresh copy of its own ship.
+/// Only one reinforcement warps in per round.
+fn reinforce(statics: &Statics, round: &Round, before: &[Entity], output: &mut Output) {
+    let caller = sorted(&round.instructions)
+        .into_iter()
+        .filter(|(_, instructions)| {
+            instructions.contains(&Instruction::Npc(NpcInstruction::CallReinforcements))
+        })
+        .find_map(|(index, _)| match before.get(index) {
+            Some(Entity::Npc(npc)) => Some(npc),
+            _ => None,
+        });
+    if let Some((faction, ship)) = caller {
+        let reinforcement = Entity::Npc((*faction, Ship::new(statics, ship.fitting.clone())));
+        output.log.push(Log::WarpIn((&reinforcement).into()));
+        output.remaining.push(reinforcement);
+    }
+}
+
+fn is_guarded(site_entities: &[Entity]) -> bool {
+    site_entities
+        .iter()
+        .any(|o| matches!(o, Entity::Npc((NpcFaction::Guards, _))))
+}
+
+/// Players which attacked other players or guards with the index of their target.
+fn attacks(round: &Round, before: &[Entity]) -> Vec<(Player, usize)> {
+    let mut attacks = Vec::new();
+    for (index, instructions) in sorted(&round.instructions) {
+        let player = match before.get(index) {
+            Some(Entity::Player((player, _))) => *player,
+            _ => continue,
+        };
+        for instruction in instructions {
+            if let Instruction::Typings(TypingsInstruction::ModuleTargeted(m)) = instruction {
+                let target = usize::from(m.target_index_in_site);
+                let attackable = matches!(
+                    before.get(target),
+                    Some(Entity::Player(_) | Entity::Npc((NpcFaction::Guards, _)))
+                );
+                if target != index && attackable && !attacks.contains(&(player, target)) {
+                    attacks.push((player, target));
+                }
+            }
+        }
+    }
+    attacks
+}
+
+/// Flag players attacking others without a reason and give their victims a kill right.
+/// Attacking criminals, suspects or players one holds a kill right against is fine.
+///
+/// Returns the players which attacked without a reason. Attacking guards never has one.
+fn flag_aggression(
+    persist: &mut Persist,
+    tick: u64,
+    guarded: bool,
+    before: &[Entity],
+    attacks: &[(Player, usize)],
+) -> anyhow::Result<Vec<Player>> {
+    let mut aggressors = Vec::new();
+    for (attacker, target) in attacks.iter().copied() {
+        let victim = match before.get(target) {
+            Some(Entity::Player((victim, _))) if victim != &attacker => *victim,
+            Some(Entity::Npc(_)) => {
+                if !aggressors.contains(&attacker) {
+                    aggressors.push(attacker);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let mut attacker_flags = persist.player_flags.read(attacker);
+        let mut victim_flags = persist.player_flags.read(victim);
+        let provoked = victim_flags.is_criminal(tick)
+            || victim_flags.is_suspect(tick)
+            || attacker_flags.has_kill_right(victim, tick);
+        if provoked {
+            continue;
+        }
+        if !aggressors.contains(&attacker) {
+            aggressors.push(attacker);
+        }
+        if guarded {
+            attacker_flags.criminal_until = Some(tick + CRIMINAL_TICKS);
+        } else {
+            attacker_flags.suspect_until = Some(tick + SUSPECT_TICKS);
+        }
+        victim_flags.kill_rights.retain(|o| o.against != attacker);
+        victim_flags.kill_rights.push(KillRight {
+            against: attacker,
+            until: tick + KILL_RIGHT_TICKS,
+        });
+        persist.player_flags.write(attacker, tick, attacker_flags)?;
+        persist.player_flags.write(victim, tick, victim_flags)?;
+    }
+    Ok(aggressors)
+}
+
+/// The payout is limited to the modules still fitted when the ship was destroyed
+/// so stripping an insured ship before losing it does not pay.
+fn claim_insurance(
+    statics: &Statics,
+    persist: &mut Persist,
+    tick: u64,
+    player: Player,
+    ship: Option<&Ship>,
+) -> anyhow::Result<()> {
+    if let Some(insured) = persist.player_insurances.claim(player)? {
+        let fitted = ship.map_or(0, |ship| station::insurance_payout(statics, &ship.fitting));
+        let payout = insured.min(fitted);
+        if payout == 0 {
+            return Ok(());
+        }
+        let mut general = persist.player_generals.read(player);
+        general.paperclips = general.paperclips.saturating_add(payout);
+        persist.player_generals.write(player, &general)?;
+        persist.player_notifications.add_message(
+            player,
+            tick,
+            format!("Your insurance paid out {} paperclips.", payout),
+        )?;
+    }
+    Ok(())
+}
+
+/// Players looting a wreck take as much of it as fits into their cargo.
+/// Only players still in the site at the end of the round can loot.
+fn loot(
+    statics: &Statics,
+    round: &Round,
+    before: &[Entity],
+    remaining: &mut [Entity],
+    wrecks: &mut [Wreck],
+) {
+    for (index, instructions) in sorted(&round.instructions) {
+        let player = match before.get(index) {
+            Some(Entity::Player((player, _))) => *player,
+            _ => continue,
+        };
+        let ship = remaining.iter_mut().find_map(|entity| match entity {
+            Entity::Player((p, ship)) if p == &player => Some(ship),
+            _ => None,
+        });
+        if let Some(ship) = ship {
+            for instruction in instructions {
+                if let Instruction::Backend(BackendInstruction::Loot { wreck_index }) = instruction
+                {
+                    if let Some(wreck) = wrecks.get_mut(usize::from(wreck_index)) {
+                        site::loot(statics, ship, wreck);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Ships which were destroyed within the round.
+///
+/// The typings only report the dead players.
+/// The remaining entities keep their order so every entity before the round
+/// is followed through the remaining ones by its index.
+/// NPCs missing from them either warped out as instructed or were destroyed.
+fn destroyed(
+    before: &[Entity],
+    instructions: &HashMap<usize, Vec<TypingsInstruction>>,
+    remaining: &[Entity],
+    warped: &[Entity],
+    dead: &[Player],
+) -> Vec<Entity> {
+    let mut remaining = remaining.iter().peekable();
+    let mut warped = warped.iter().collect::<Vec<_>>();
+    let mut destroyed = Vec::new();
+    for (index, entity) in before.iter().enumerate() {
+        if matches!(remaining.peek(), Some(o) if is_same(entity, o)) {
+            remaining.next();
+            continue;
+        }
+        match entity {
+            Entity::Player((player, _)) => {
+                if dead.contains(player) {
+                    destroyed.push(entity.clone());
+                }
+            }
+            Entity::Npc(_) => {
+                let warping = instructions
+                    .get(&index)
+                    .into_iter()
+                    .flatten()
+                    .any(|o| matches!(o, TypingsInstruction::Warp(_)));
+                let position = warped.iter().position(|o| is_same(entity, o));
+                match position {
+                    Some(position) if warping => {
+                        warped.remove(position);
+                    }
+                    _ => destroyed.push(entity.clone()),
+                }
+            }
+            Entity::Asteroid(_) | Entity::Facility(_) => {}
+        }
+    }
+    destroyed
+}
+
+/// Whether both are the same entity before and after a round changed its collateral or cargo.
+fn is_same(before: &Entity, after: &Entity) -> bool {
+    match (before, after) {
+        (Entity::Player((a, _)), Entity::Player((b, _))) => a == b,
+        (Entity::Npc((fa, a)), Entity::Npc((fb, b))) => fa == fb && a.fitting == b.fitting,
+        (Entity::Asteroid(a), Entity::Asteroid(b)) => a.ore == b.ore,
+        (Entity::Facility(a), Entity::Facility(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Warping within the orbit of a planet takes one tick.
+/// Every planet further away takes another one.
+/// Jumping into another solarsystem through a stargate takes one tick.
+fn warp_ticks(
+    persist: &Persist,
+    solarsystem: Solarsystem,
+    site: Site,
+    target_solarsystem: Solarsystem,
+    target: Site,
+) -> u64 {
+    if solarsystem != target_solarsystem {
+        return 1;
+    }
+    let from = persist.sites.read_planet(solarsystem, site);
+    let to = persist.sites.read_planet(solarsystem, target);
+    if let (Some(from), Some(to)) = (from, to) {
+        1 + u64::from(from.max(to) - from.min(to))
+    } else {
+        1
+    }
+}
+
+#[test]
+fn orphaned_warps_land_at_home() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    let backend: std::sync::Arc<dyn crate::persist::Backend> =
+        std::sync::Arc::new(crate::persist::Memory::default());
+    let mut persist = Persist::new(backend);
+    let solarsystem = Solarsystem::Vosu;
+    let player = Player::Telegram(42);
+    let home = PlayerLocationStation {
+        solarsystem,
+        station: 1,
+    };
+    persist
+        .player_generals
+        .write_home(player, Some(home))
+        .unwrap();
+    let existing = Site::AsteroidField(1);
+    let gone = Site::AsteroidField(2);
+    for (towards, entity) in [
+        (gone, Entity::Player((player, Ship::default()))),
+        (gone, Entity::Npc((NpcFaction::Pirates, Ship::default()))),
+        (
+            existing,
+            Entity::Npc((NpcFaction::Pirates, Ship::default())),
+        ),
+    ] {
+        persist
+            .sites
+            .add_entity_warping(
+                solarsystem,
+                Warping {
+                    towards,
+                    entity,
+                    departure: 0,
+                    arrival: 5,
+                },
+            )
+            .unwrap();
+    }
+
+    land_orphans(&mut persist, solarsystem, &[existing]).unwrap();
+
+    let warping = persist.sites.read_entitiy_warping(solarsystem);
+    assert_eq!(warping.len(), 1);
+    assert_eq!(warping[0].towards, existing);
+    assert_eq!(
+        persist.player_locations.read(player),
+        PlayerLocation::Station(home)
+    );
+    let assets = persist
+        .player_station_assets
+        .read(player, solarsystem, home.station);
+    assert_eq!(assets.current_ship, Some(Ship::default()));
+}
+
+#[test]
+fn provoked_attacks_are_no_aggression() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    let backend: std::sync::Arc<dyn crate::persist::Backend> =
+        std::sync::Arc::new(crate::persist::Memory::default());
+    let mut persist = Persist::new(backend);
+    let tick = 10;
+    let suspect = Player::Telegram(1);
+    let attacker = Player::Telegram(2);
+    let mut flags = persist.player_flags.read(suspect);
+    flags.suspect_until = Some(tick + 5);
+    persist.player_flags.write(suspect, tick, flags).unwrap();
+    let before = [
+        Entity::Player((suspect, Ship::default())),
+        Entity::Player((attacker, Ship::default())),
+        Entity::Npc((NpcFaction::Guards, Ship::default())),
+    ];
+
+    let aggressors = flag_aggression(&mut persist, tick, true, &before, &[(attacker, 0)]).unwrap();
+    assert!(aggressors.is_empty());
+    assert!(!persist.player_flags.read(attacker).is_criminal(tick));
+
+    let aggressors = flag_aggression(&mut persist, tick, true, &before, &[(attacker, 2)]).unwrap();
+    assert_eq!(aggressors, [attacker]);
+}
+
+#[test]
+fn destroyed_follows_entities_by_index() {
+    let player = Player::Telegram(42);
+    let pirate = || Entity::Npc((NpcFaction::Pirates, Ship::default()));
+    let before = [
+        Entity::Player((player, Ship::default())),
+        pirate(),
+        pirate(),
+    ];
+    let warp = HashMap::from([(2, vec![TypingsInstruction::Warp(Site::AsteroidField(1))])]);
+
+    // The second pirate warped out, the first one was destroyed
+    let destroyed_pirates = destroyed(&before, &warp, &before[..1], &[pirate()], &[]);
+    assert_eq!(destroyed_pirates, [pirate()]);
+
+    // Both pirates survived, one of them warped out
+    let survived = destroyed(&before, &warp, &before[..2], &[pirate()], &[]);
+    assert!(survived.is_empty());
+
+    // Without the instruction the warped pirate can not be one of them
+    let destroyed_all = destroyed(&before, &HashMap::new(), &[], &[pirate()], &[player]);
+    assert_eq!(destroyed_all, before);
+}
+
+#[test]
+fn stripped_ships_claim_only_their_fitted_modules() {
+    use space_game_typings::fixed::module::Passive;
+    use space_game_typings::station::instruction::Instruction as StationInstruction;
+    let statics = Statics::default();
+    let backend: std::sync::Arc<dyn crate::persist::Backend> =
+        std::sync::Arc::new(crate::persist::Memory::default());
+    let mut persist = Persist::new(backend);
+    let solarsystem = Solarsystem::Vosu;
+    let station = 0;
+    let player = Player::Telegram(42);
+    persist
+        .player_locations
+        .write(
+            player,
+            PlayerLocation::Station(PlayerLocationStation {
+                solarsystem,
+                station,
+            }),
+        )
+        .unwrap();
+    let mut general = persist.player_generals.read(player);
+    general.paperclips = 1000;
+    persist.player_generals.write(player, &general).unwrap();
+    let mut ship = Ship::default();
+    let extra = ship.fitting.slots_passive.len();
+    ship.fitting.slots_passive.push(Passive::RookieArmorPlate);
+    let mut assets = persist
+        .player_station_assets
+        .read(player, solarsystem, station);
+    assets.current_ship = Some(ship.clone());
+    persist
+        .player_station_assets
+        .write(player, solarsystem, station, &assets)
+        .unwrap();
+
+    station::do_instructions(
+        &statics,
+        &mut persist,
+        player,
+        &[
+            station::Instruction::Backend(station::BackendInstruction::Insure),
+            station::Instruction::Typings(StationInstruction::ModulePassiveRemove(
+                u8::try_from(extra).unwrap(),
+            )),
+        ],
+    )
+    .unwrap();
+    let before = persist.player_generals.read(player).paperclips;
+    assert!(before < 1000);
+    let stripped = persist
+        .player_station_assets
+        .read(player, solarsystem, station)
+        .current_ship
+        .unwrap();
+    assert_eq!(station::insurance_payout(&statics, &stripped.fitting), 0);
+
+    persist
+        .player_insurances
+        .undock(player, solarsystem, station)
+        .unwrap();
+    claim_insurance(&statics, &mut persist, 1, player, Some(&stripped)).unwrap();
+    assert_eq!(persist.player_generals.read(player).paperclips, before);
+    assert!(persist.player_insurances.read(player).is_empty());
+}
@@ -0,0 +1,328 @@
+use std::cmp::Reverse;
+
+use space_game_typings::player::Player;
+use space_game_typings::ship::{Collateral, Ship};
+use space_game_typings::site::instruction::{Instruction as TypingsInstruction, UseModuleTargeted};
+use space_game_typings::site::Entity;
+
+use super::{Context, Npc};
+use crate::site::{Instruction, NpcInstruction};
+
+/// Something an NPC might do.
+pub trait Behavior {
+    /// Instructions of the NPC or `None` when the behavior does not apply.
+    fn decide(&self, npc: &Npc, context: &Context) -> Option<Vec<Instruction>>;
+}
+
+/// Players an NPC considers hostile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Targets {
+    Players,
+    Outlaws,
+}
+
+impl Targets {
+    /// Index in the site and ship of every hostile player.
+    pub fn find<'a>(self, context: &Context<'a>) -> Vec<(usize, Player, &'a Ship)> {
+        context
+            .site_entities
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entity)| match entity {
+                Entity::Player((player, ship))
+                    if self == Self::Players || context.outlaws.contains(player) =>
+                {
+                    Some((index, *player, ship))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Which of the hostiles is attacked first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The one with the most targeted modules
+    Threat,
+    /// The one closest to being destroyed
+    LowestCollateral,
+}
+
+/// Fire every targeted module at the hostile with the highest priority.
+pub struct Attack {
+    pub targets: Targets,
+    pub priority: Priority,
+}
+
+impl Behavior for Attack {
+    fn decide(&self, npc: &Npc, context: &Context) -> Option<Vec<Instruction>> {
+        let hostiles = self.targets.find(context);
+        // min_by_key keeps the first one in the site on a tie
+        let target = match self.priority {
+            Priority::Threat => hostiles
+                .iter()
+                .min_by_key(|(_, _, ship)| Reverse(threat(ship))),
+            Priority::LowestCollateral => hostiles
+                .iter()
+                .min_by_key(|(_, _, ship)| remaining(ship.collateral)),
+        }?;
+        Some(fire_all(npc.ship, target.0))
+    }
+}
+
+/// Warp away when the ship is below the given share of its maximum collateral.
+pub struct Flee {
+    pub below: f32,
+}
+
+impl Behavior for Flee {
+    #[allow(clippy::cast_precision_loss)]
+    fn decide(&self, npc: &Npc, context: &Context) -> Option<Vec<Instruction>> {
+        let maximum = remaining(npc.ship.fitting.maximum_collateral(context.statics));
+        let share = if maximum == 0 {
+            1.0
+        } else {
+            remaining(npc.ship.collateral) as f32 / maximum as f32
+        };
+        if share < self.below {
+            warp_out(context)
+        } else {
+            None
+        }
+    }
+}
+
+/// Warp away as soon as there is something hostile.
+pub struct WarpOut {
+    pub hostiles: Targets,
+}
+
+impl Behavior for WarpOut {
+    fn decide(&self, _npc: &Npc, context: &Context) -> Option<Vec<Instruction>> {
+        if self.hostiles.find(context).is_empty() {
+            None
+        } else {
+            warp_out(context)
+        }
+    }
+}
+
+/// Call another ship of the own faction when outnumbered.
+/// The call takes the whole round of the NPC.
+pub struct CallReinforcements {
+    pub hostiles: Targets,
+    /// Do not call when there are already this many of the own faction
+    pub max_allies: usize,
+}
+
+impl Behavior for CallReinforcements {
+    fn decide(&self, npc: &Npc, context: &Context) -> Option<Vec<Instruction>> {
+        let allies = context
+            .site_entities
+            .iter()
+            .filter(|o| matches!(o, Entity::Npc((faction, _)) if faction == &npc.faction))
+            .count();
+        let hostiles = self.hostiles.find(context).len();
+        if hostiles > allies && allies < self.max_allies {
+            Some(vec![Instruction::Npc(NpcInstruction::CallReinforcements)])
+        } else {
+            None
+        }
+    }
+}
+
+fn threat(ship: &Ship) -> usize {
+    ship.fitting.slots_targeted.len()
+}
+
+fn remaining(collateral: Collateral) -> u32 {
+    u32::from(collateral.armor) + u32::from(collateral.structure)
+}
+
+fn warp_out(context: &Context) -> Option<Vec<Instruction>> {
+    let escape = context.escapes.first()?;
+    Some(vec![Instruction::Typings(TypingsInstruction::Warp(
+        *escape,
+    ))])
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn fire_all(ship: &Ship, target_index: usize) -> Vec<Instruction> {
+    (0..ship.fitting.slots_targeted.len())
+        .map(|module_index| {
+            Instruction::Typings(TypingsInstruction::ModuleTargeted(UseModuleTargeted {
+                target_index_in_site: target_index as u8,
+                module_index: module_index as u8,
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn context<'a>(
+    statics: &'a space_game_typings::fixed::Statics,
+    site_entities: &'a [Entity],
+    escapes: &'a [space_game_typings::site::Site],
+) -> Context<'a> {
+    Context {
+        statics,
+        site_entities,
+        outlaws: &[],
+        escapes,
+    }
+}
+
+#[cfg(test)]
+fn pirate(entities: &[Entity], index: usize) -> Npc<'_> {
+    match &entities[index] {
+        Entity::Npc((faction, ship)) => Npc {
+            faction: *faction,
+            ship,
+        },
+        _ => panic!("not an npc"),
+    }
+}
+
+#[cfg(test)]
+fn target_of(instructions: &[Instruction]) -> Option<u8> {
+    match instructions.first() {
+        Some(Instruction::Typings(TypingsInstruction::ModuleTargeted(m))) => {
+            Some(m.target_index_in_site)
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn attack_the_biggest_threat() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::fixed::Statics;
+    let entities = vec![
+        Entity::Npc((NpcFaction::Pirates, super::armed(2))),
+        Entity::Player((Player::Telegram(1), super::armed(1))),
+        Entity::Player((Player::Telegram(2), super::armed(3))),
+    ];
+    let statics = Statics::default();
+    let context = context(&statics, &entities, &[]);
+    let attack = Attack {
+        targets: Targets::Players,
+        priority: Priority::Threat,
+    };
+    let instructions = attack.decide(&pirate(&entities, 0), &context).unwrap();
+    assert_eq!(instructions.len(), 2);
+    assert_eq!(target_of(&instructions), Some(2));
+}
+
+#[test]
+fn attack_the_weakest() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::fixed::Statics;
+    let mut damaged = super::armed(3);
+    let mut healthy = super::armed(1);
+    damaged.collateral = Collateral {
+        capacitor: 0,
+        armor: 0,
+        structure: 5,
+    };
+    healthy.collateral = Collateral {
+        capacitor: 0,
+        armor: 10,
+        structure: 10,
+    };
+    let entities = vec![
+        Entity::Player((Player::Telegram(1), healthy)),
+        Entity::Npc((NpcFaction::Pirates, super::armed(1))),
+        Entity::Player((Player::Telegram(2), damaged)),
+    ];
+    let statics = Statics::default();
+    let context = context(&statics, &entities, &[]);
+    let attack = Attack {
+        targets: Targets::Players,
+        priority: Priority::LowestCollateral,
+    };
+    let instructions = attack.decide(&pirate(&entities, 1), &context).unwrap();
+    assert_eq!(target_of(&instructions), Some(2));
+}
+
+#[test]
+fn nothing_to_attack() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::fixed::Statics;
+    let entities = vec![
+        Entity::Npc((NpcFaction::Guards, super::armed(1))),
+        Entity::Player((Player::Telegram(1), super::armed(1))),
+    ];
+    let statics = Statics::default();
+    let context = context(&statics, &entities, &[]);
+    let attack = Attack {
+        targets: Targets::Outlaws,
+        priority: Priority::Threat,
+    };
+    assert_eq!(attack.decide(&pirate(&entities, 0), &context), None);
+}
+
+#[test]
+fn warp_out_when_hostiles_appear() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::fixed::Statics;
+    use space_game_typings::site::Site;
+    let statics = Statics::default();
+    let escapes = [Site::AsteroidField(2)];
+    let alone = vec![Entity::Npc((NpcFaction::Pirates, Ship::default()))];
+    let warp_out = WarpOut {
+        hostiles: Targets::Players,
+    };
+    assert_eq!(
+        warp_out.decide(&pirate(&alone, 0), &context(&statics, &alone, &escapes)),
+        None
+    );
+    let visited = vec![
+        Entity::Npc((NpcFaction::Pirates, Ship::default())),
+        Entity::Player((Player::Telegram(1), Ship::default())),
+    ];
+    assert_eq!(
+        warp_out.decide(&pirate(&visited, 0), &context(&statics, &visited, &escapes)),
+        Some(vec![Instruction::Typings(TypingsInstruction::Warp(
+            Site::AsteroidField(2)
+        ))])
+    );
+    assert_eq!(
+        warp_out.decide(&pirate(&visited, 0), &context(&statics, &visited, &[])),
+        None
+    );
+}
+
+#[test]
+fn call_reinforcements_when_outnumbered() {
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::fixed::Statics;
+    let statics = Statics::default();
+    let call = CallReinforcements {
+        hostiles: Targets::Players,
+        max_allies: 2,
+    };
+    let outnumbered = vec![
+        Entity::Npc((NpcFaction::Pirates, super::armed(1))),
+        Entity::Player((Player::Telegram(1), Ship::default())),
+        Entity::Player((Player::Telegram(2), Ship::default())),
+    ];
+    assert_eq!(
+        call.decide(
+            &pirate(&outnumbered, 0),
+            &context(&statics, &outnumbered, &[])
+        ),
+        Some(vec![Instruction::Npc(NpcInstruction::CallReinforcements)])
+    );
+    let even = vec![
+        Entity::Npc((NpcFaction::Pirates, super::armed(1))),
+        Entity::Npc((NpcFaction::Pirates, super::armed(1))),
+        Entity::Player((Player::Telegram(1), Ship::default())),
+        Entity::Player((Player::Telegram(2), Ship::default())),
+        Entity::Player((Player::Telegram(3), Ship::default())),
+    ];
+    assert_eq!(
+        call.decide(&pirate(&even, 0), &context(&statics, &even, &[])),
+        None
+    );
+}
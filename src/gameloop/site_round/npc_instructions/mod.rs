@@ -0,0 +1,184 @@
+use space_game_typings::fixed::npc_faction::NpcFaction;
+use space_game_typings::fixed::Statics;
+use space_game_typings::player::Player;
+use space_game_typings::ship::Ship;
+use space_game_typings::site::{Entity, Site};
+
+use crate::site::Instruction;
+
+use self::behavior::{Attack, Behavior, CallReinforcements, Flee, Priority, Targets, WarpOut};
+
+mod behavior;
+
+/// Everything an NPC knows about its surroundings.
+pub struct Context<'a> {
+    pub statics: &'a Statics,
+    pub site_entities: &'a [Entity],
+    /// Players the guards attack like criminals
+    pub outlaws: &'a [Player],
+    /// Sites an NPC can warp to
+    pub escapes: &'a [Site],
+}
+
+/// The NPC deciding what to do.
+pub struct Npc<'a> {
+    pub faction: NpcFaction,
+    pub ship: &'a Ship,
+}
+
+/// Part an NPC plays within the group of its faction in the site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// First armed NPC of its faction
+    Leader,
+    /// Every other armed NPC
+    Wingman,
+    /// Without targeted modules
+    Unarmed,
+}
+
+impl Role {
+    /// `None` when the entity is not an NPC.
+    pub fn of(site_entities: &[Entity], index: usize) -> Option<Self> {
+        if let Some(Entity::Npc((faction, ship))) = site_entities.get(index) {
+            if ship.fitting.slots_targeted.is_empty() {
+                return Some(Self::Unarmed);
+            }
+            let first_armed = site_entities.iter().position(|o| {
+                matches!(o, Entity::Npc((f, s)) if f == faction && !s.fitting.slots_targeted.is_empty())
+            });
+            if first_armed == Some(index) {
+                Some(Self::Leader)
+            } else {
+                Some(Self::Wingman)
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Behaviors of the NPC ordered by priority.
+/// The first one deciding on something is done by the NPC.
+pub fn behaviors(faction: NpcFaction, role: Role) -> Vec<Box<dyn Behavior>> {
+    match (faction, role) {
+        (NpcFaction::Guards, Role::Leader) => vec![
+            Box::new(CallReinforcements {
+                hostiles: Targets::Outlaws,
+                max_allies: 6,
+            }),
+            Box::new(Attack {
+                targets: Targets::Outlaws,
+                priority: Priority::LowestCollateral,
+            }),
+        ],
+        (NpcFaction::Guards, Role::Wingman) => vec![Box::new(Attack {
+            targets: Targets::Outlaws,
+            priority: Priority::LowestCollateral,
+        })],
+        (NpcFaction::Guards, Role::Unarmed) => vec![],
+        (NpcFaction::Pirates, Role::Leader) => vec![
+            Box::new(Flee { below: 0.25 }),
+            Box::new(CallReinforcements {
+                hostiles: Targets::Players,
+                max_allies: 4,
+            }),
+            Box::new(Attack {
+                targets: Targets::Players,
+                priority: Priority::Threat,
+            }),
+        ],
+        (NpcFaction::Pirates, Role::Wingman) => vec![
+            Box::new(Flee { below: 0.25 }),
+            Box::new(Attack {
+                targets: Targets::Players,
+                priority: Priority::Threat,
+            }),
+        ],
+        (NpcFaction::Pirates, Role::Unarmed) => vec![Box::new(WarpOut {
+            hostiles: Targets::Players,
+        })],
+    }
+}
+
+pub fn generate(context: &Context) -> Vec<(usize, Vec<Instruction>)> {
+    let mut result = Vec::new();
+    for (index, entity) in context.site_entities.iter().enumerate() {
+        if let (Entity::Npc((faction, ship)), Some(role)) =
+            (entity, Role::of(context.site_entities, index))
+        {
+            let npc = Npc {
+                faction: *faction,
+                ship,
+            };
+            let instructions = behaviors(*faction, role)
+                .iter()
+                .find_map(|behavior| behavior.decide(&npc, context))
+                .unwrap_or_default();
+            result.push((index, instructions));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+fn armed(amount: usize) -> Ship {
+    use space_game_typings::fixed::module::Targeted;
+    use space_game_typings::ship::Fitting;
+    Ship {
+        fitting: Fitting {
+            slots_targeted: vec![Targeted::RookieLaser; amount],
+            ..Fitting::default()
+        },
+        ..Ship::default()
+    }
+}
+
+#[test]
+fn roles_within_the_faction() {
+    let entities = vec![
+        Entity::Npc((NpcFaction::Pirates, Ship::default())),
+        Entity::Npc((NpcFaction::Pirates, armed(1))),
+        Entity::Npc((NpcFaction::Guards, armed(1))),
+        Entity::Npc((NpcFaction::Pirates, armed(1))),
+        Entity::Player((Player::Telegram(42), armed(1))),
+    ];
+    assert_eq!(Role::of(&entities, 0), Some(Role::Unarmed));
+    assert_eq!(Role::of(&entities, 1), Some(Role::Leader));
+    assert_eq!(Role::of(&entities, 2), Some(Role::Leader));
+    assert_eq!(Role::of(&entities, 3), Some(Role::Wingman));
+    assert_eq!(Role::of(&entities, 4), None);
+}
+
+#[test]
+fn guards_only_attack_outlaws() {
+    use space_game_typings::site::instruction::{
+        Instruction as TypingsInstruction, UseModuleTargeted,
+    };
+    let good = Player::Telegram(1);
+    let bad = Player::Telegram(2);
+    let entities = vec![
+        Entity::Npc((NpcFaction::Guards, armed(1))),
+        Entity::Npc((NpcFaction::Guards, armed(1))),
+        Entity::Player((good, Ship::default())),
+        Entity::Player((bad, Ship::default())),
+    ];
+    let statics = Statics::default();
+    let mut context = Context {
+        statics: &statics,
+        site_entities: &entities,
+        outlaws: &[],
+        escapes: &[],
+    };
+    assert_eq!(generate(&context), vec![(0, vec![]), (1, vec![])]);
+    let outlaws = [bad];
+    context.outlaws = &outlaws;
+    let expected = Instruction::Typings(TypingsInstruction::ModuleTargeted(UseModuleTargeted {
+        target_index_in_site: 3,
+        module_index: 0,
+    }));
+    assert_eq!(
+        generate(&context),
+        vec![(0, vec![expected]), (1, vec![expected])]
+    );
+}
@@ -1,17 +1,13 @@
 use rand::Rng;
-use space_game_typings::fixed::item::Ore;
-use space_game_typings::fixed::module::Targeted;
-use space_game_typings::fixed::npc_faction::NpcFaction;
-use space_game_typings::fixed::shiplayout::ShipLayout;
 use space_game_typings::fixed::solarsystem::Solarsystem;
 use space_game_typings::fixed::Statics;
-use space_game_typings::ship::{Fitting, Ship};
 use space_game_typings::site::{Entity, Site, SitesNearPlanet};
 
+use crate::persist::journal::Event;
 use crate::persist::Persist;
+use crate::spawn_tables::{SpawnTable, SpawnTables};
 
-fn generate_unique(existing: &mut Vec<u8>) -> u8 {
-    let mut rng = rand::thread_rng();
+fn generate_unique(rng: &mut impl Rng, existing: &mut Vec<u8>) -> u8 {
     loop {
         let unique = rng.gen();
         if !existing.contains(&unique) {
@@ -21,16 +17,33 @@ fn generate_unique(existing: &mut Vec<u8>) -> u8 {
     }
 }
 
-pub fn all(statics: &Statics, persist: &mut Persist) -> anyhow::Result<()> {
-    for solarsystem in statics.solarsystems.data.keys().copied() {
-        let sites = persist
-            .sites
-            .read_sites(solarsystem)
-            .expect("init at least created gate sites");
+pub fn all(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    persist: &mut Persist,
+    rng: &mut impl Rng,
+) -> anyhow::Result<()> {
+    // HashMap order differs between runs which would mix up the random numbers
+    let mut solarsystems = statics
+        .solarsystems
+        .data
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    solarsystems.sort_by_key(ToString::to_string);
+    for solarsystem in solarsystems {
+        let sites = match persist.sites.read_sites(solarsystem) {
+            Ok(sites) => sites,
+            Err(err) => {
+                eprintln!("ERROR gameloop::sites {} {}", solarsystem, err);
+                continue;
+            }
+        };
+        let table = spawn_tables.get(statics, solarsystem);
 
         // Asteroid Belts
-        generate_asteroid_belts(statics, persist, solarsystem, &sites)?;
-        spawn_asteroid_belt_pirates(statics, persist, solarsystem, &sites)?;
+        generate_asteroid_belts(statics, table, persist, rng, solarsystem, &sites)?;
+        spawn_asteroid_belt_pirates(statics, table, persist, rng, solarsystem, &sites)?;
     }
 
     Ok(())
@@ -38,7 +51,9 @@ pub fn all(statics: &Statics, persist: &mut Persist) -> anyhow::Result<()> {
 
 fn generate_asteroid_belts(
     statics: &Statics,
+    table: &SpawnTable,
     persist: &mut Persist,
+    rng: &mut impl Rng,
     solarsystem: Solarsystem,
     sites: &SitesNearPlanet,
 ) -> anyhow::Result<()> {
@@ -54,32 +69,33 @@ fn generate_asteroid_belts(
             }
         })
         .collect::<Vec<_>>();
-    let mut rng = rand::thread_rng();
-    for _ in existing.len()..4 {
+    for _ in existing.len()..usize::from(table.asteroid_fields.amount) {
         let planet = rng.gen_range(1..=planets);
-        let site = Site::AsteroidField(generate_unique(&mut existing));
-        let entities = vec![
-            Entity::new_asteroid(Ore::Aromit, 25, 18),
-            Entity::new_asteroid(Ore::Aromit, 40, 25),
-            Entity::new_asteroid(Ore::Solmit, 15, 120),
-            Entity::new_asteroid(Ore::Tormit, 10, 12),
-            Entity::new_asteroid(Ore::Vesmit, 6, 4),
-        ];
+        let site = Site::AsteroidField(generate_unique(rng, &mut existing));
+        let entities = table.belt_asteroids(rng);
         persist
             .sites
             .add_site(solarsystem, planet, site, &entities)?;
+        persist.journal.record(Event::SiteAdded {
+            solarsystem,
+            planet,
+            site,
+            entities,
+        });
     }
 
     Ok(())
 }
 
+/// Once players cleared a wave the next one follows soon while they stay.
 fn spawn_asteroid_belt_pirates(
     statics: &Statics,
+    table: &SpawnTable,
     persist: &mut Persist,
+    rng: &mut impl Rng,
     solarsystem: Solarsystem,
     sites: &SitesNearPlanet,
 ) -> anyhow::Result<()> {
-    let mut rng = rand::thread_rng();
     for site in sites.all() {
         if let Site::AsteroidField(_) = site {
             let mut entities = persist.sites.read_entities(solarsystem, site)?;
@@ -88,19 +104,23 @@ fn spawn_asteroid_belt_pirates(
                 .iter()
                 .filter(|o| matches!(o, Entity::Npc(_)))
                 .count();
+            let players = entities.iter().any(|o| matches!(o, Entity::Player(_)));
+            let wave = persist.sites.read_pirates(solarsystem, site).wave;
 
-            if npc_amount == 0 && rng.gen_range(0..30) == 0 {
-                let fitting = Fitting {
-                    layout: ShipLayout::Hecate,
-                    slots_targeted: vec![Targeted::RookieLaser],
-                    slots_untargeted: vec![],
-                    slots_passive: vec![],
-                };
-                entities.push(Entity::Npc((
-                    NpcFaction::Pirates,
-                    Ship::new(statics, fitting),
-                )));
+            let chance = if players && wave > 0 {
+                table.pirates.next_wave_chance
+            } else {
+                table.pirates.chance
+            };
+            if npc_amount == 0 && rng.gen_range(0..chance) == 0 {
+                let pirates = table.pirate_wave(statics, wave);
+                entities.extend(pirates.iter().cloned());
                 persist.sites.write_entities(solarsystem, site, &entities)?;
+                persist.journal.record(Event::EntitiesSpawned {
+                    solarsystem,
+                    site,
+                    entities: pirates,
+                });
             }
         }
     }
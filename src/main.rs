@@ -3,30 +3,91 @@
 use std::sync::Arc;
 use std::time::Instant;
 
-use async_std::sync::Mutex;
+use async_std::sync::{Mutex, RwLock};
 use space_game_typings::fixed::Statics;
 
+mod config;
 mod gameloop;
 mod persist;
+mod site;
+mod spawn_tables;
 mod station;
 mod webserver;
 
 #[async_std::main]
 async fn main() -> anyhow::Result<()> {
+    let arguments = config::load()?;
+    let config = arguments.config;
+    config::set_log_level(config.log_level);
+    println!("config {:?}", config);
+
     let app = {
         println!("load static data...");
         let measure = Instant::now();
         let statics = Arc::new(Statics::default());
         println!("  took {:?}", measure.elapsed());
 
+        println!("load spawn tables...");
+        let measure = Instant::now();
+        let spawn_tables = spawn_tables::SpawnTables::load(config.spawn_tables.as_deref())?;
+        spawn_tables.validate(&statics)?;
+        let spawn_tables = Arc::new(spawn_tables);
+        println!("  took {:?}", measure.elapsed());
+
         println!("load persist data...");
         let measure = Instant::now();
-        let mut persist = persist::Persist::default();
+        let backend: Arc<dyn persist::Backend> = if let Some(file) = &config.sqlite {
+            Arc::new(persist::Cache::new(persist::Sqlite::open(file)?))
+        } else {
+            let files = persist::YamlFiles::new(&config.persist);
+            for file in files.recover()? {
+                eprintln!("    quarantined broken persist file {:?}", file);
+            }
+            Arc::new(persist::Cache::new(files))
+        };
         println!("  took {:?}", measure.elapsed());
 
+        if let Some(file) = &arguments.restore {
+            println!("restore persist snapshot {:?}...", file);
+            let measure = Instant::now();
+            let restored = persist::snapshot::restore(&*backend, file)?;
+            println!("  restored {} files", restored);
+            println!("  took {:?}", measure.elapsed());
+        }
+
+        if arguments.replay.is_some() && !backend.list("").is_empty() {
+            anyhow::bail!("replaying a journal requires an empty persist");
+        }
+
+        println!("persist migrate...");
+        let measure = Instant::now();
+        persist::migrate(&*backend)?;
+        let mut persist = persist::Persist::new(backend);
+        println!("  took {:?}", measure.elapsed());
+
+        if let Some(file) = &arguments.replay {
+            println!("replay journal {:?}...", file);
+            let measure = Instant::now();
+            let events = persist::journal::read(file)?;
+            gameloop::replay(&statics, &spawn_tables, &mut persist, events)?;
+            println!("  took {:?}", measure.elapsed());
+            return Ok(());
+        }
+        persist.journal = persist::Journal::open(&config.journal())?;
+        persist
+            .journal
+            .record(persist::journal::Event::SpawnTables {
+                tables: (*spawn_tables).clone(),
+            });
+        if let Some(seed) = config.seed {
+            if persist.random.read_seed().is_none() {
+                persist.random.write_seed(seed)?;
+            }
+        }
+
         println!("persist ensure_statics...");
         let measure = Instant::now();
-        persist::ensure_static_sites(&statics, &mut persist.sites).unwrap();
+        persist::ensure_static_sites(&statics, &spawn_tables, &mut persist.sites).unwrap();
         println!("  took {:?}", measure.elapsed());
 
         println!("persist ensure_player_locations...");
@@ -34,12 +95,18 @@ async fn main() -> anyhow::Result<()> {
         persist::ensure_player_locations(&statics, &mut persist).unwrap();
         println!("  took {:?}", measure.elapsed());
 
+        let view = Arc::new(RwLock::new(persist.published_view()));
         let persist = Arc::new(Mutex::new(persist));
+        let gameloop_control = Arc::new(gameloop::Control::new(config.tick_interval()));
 
         println!("init webserver...");
         let app_state = webserver::State {
             statics: statics.clone(),
+            spawn_tables: spawn_tables.clone(),
             persist: persist.clone(),
+            view: view.clone(),
+            gameloop: gameloop_control.clone(),
+            admin_token: config.admin_token.as_deref().map(Arc::from),
         };
         let measure = Instant::now();
         let app = webserver::init(app_state);
@@ -47,15 +114,22 @@ async fn main() -> anyhow::Result<()> {
 
         println!("start gameloop...");
         let measure = Instant::now();
-        gameloop::start(statics, persist)
-            .await
-            .expect("first gameloop iteration failed");
+        gameloop::start(
+            statics,
+            spawn_tables,
+            persist,
+            view,
+            gameloop_control,
+            config.snapshot_settings(),
+        )
+        .await
+        .expect("first gameloop iteration failed");
         println!("  took {:?}", measure.elapsed());
 
         app
     };
 
-    println!("Starting to listen on http://localhost:8080");
-    app.listen("[::]:8080").await?; // Works for both IPv4 and IPv6
+    println!("Starting to listen on {}", config.listen);
+    app.listen(config.listen).await?;
     Ok(())
 }
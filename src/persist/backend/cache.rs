@@ -0,0 +1,330 @@
+use std::any::Any;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+use super::Backend;
+
+pub type Decoded = Arc<dyn Any + Send + Sync>;
+
+#[derive(Clone)]
+struct Entry {
+    /// `None` when the key does not exist (anymore).
+    content: Option<String>,
+    decoded: Option<Decoded>,
+    /// Changed since the last flush.
+    dirty: bool,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    /// Entries as they were before the running transaction changed them.
+    transaction: Option<HashMap<String, Option<Entry>>>,
+    /// Content as readers of the published view see it while changes are staged.
+    published: Option<HashMap<String, Option<String>>>,
+}
+
+impl State {
+    fn remember_before_change(&mut self, key: &str) {
+        if let Some(transaction) = &mut self.transaction {
+            if !transaction.contains_key(key) {
+                transaction.insert(key.to_string(), self.entries.get(key).cloned());
+            }
+        }
+    }
+}
+
+/// Keeps read values in memory and writes changes only on `flush`.
+///
+/// Decoded values are kept too so the same file is not deserialized over and over again.
+pub struct Cache<B> {
+    inner: B,
+    state: RwLock<State>,
+}
+
+impl<B: Backend> Cache<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            state: RwLock::default(),
+        }
+    }
+
+    fn set(&self, key: &str, content: Option<String>) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.remember_before_change(key);
+        let unpublished =
+            matches!(&state.published, Some(published) if !published.contains_key(key));
+        if unpublished {
+            let before = if let Some(entry) = state.entries.get(key) {
+                entry.content.clone()
+            } else {
+                self.inner.read(key)?
+            };
+            if let Some(published) = &mut state.published {
+                published.insert(key.to_string(), before);
+            }
+        }
+        state.entries.insert(
+            key.to_string(),
+            Entry {
+                content,
+                decoded: None,
+                dirty: true,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl<B: Backend> Backend for Cache<B> {
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        if let Some(entry) = self.state.read().unwrap().entries.get(key) {
+            return Ok(entry.content.clone());
+        }
+        let content = self.inner.read(key)?;
+        // A parallel write might have happened since
+        let mut state = self.state.write().unwrap();
+        let entry = state.entries.entry(key.to_string()).or_insert(Entry {
+            content,
+            decoded: None,
+            dirty: false,
+        });
+        Ok(entry.content.clone())
+    }
+
+    fn write(&self, key: &str, content: &str) -> Result<()> {
+        self.set(key, Some(content.to_string()))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.set(key, None)
+    }
+
+    fn list(&self, folder: &str) -> Vec<String> {
+        let prefix = super::folder_prefix(folder);
+        let state = self.state.read().unwrap();
+        let mut result = self
+            .inner
+            .list(folder)
+            .into_iter()
+            .filter(|key| !matches!(state.entries.get(key), Some(Entry { content: None, .. })))
+            .collect::<BTreeSet<_>>();
+        for (key, entry) in &state.entries {
+            if entry.content.is_some() && key.starts_with(&prefix) {
+                result.insert(key.clone());
+            }
+        }
+        result.into_iter().collect()
+    }
+
+    fn begin(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.transaction.is_some() {
+            return Err(anyhow::anyhow!("cache transactions can not be nested"));
+        }
+        state.transaction = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.state.write().unwrap().transaction = None;
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let before = state.transaction.take().unwrap_or_default();
+        for (key, entry) in before {
+            if let Some(entry) = entry {
+                state.entries.insert(key, entry);
+            } else {
+                state.entries.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.transaction.is_some() {
+            return Err(anyhow::anyhow!("cache can not flush within a transaction"));
+        }
+
+        let dirty = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(key, entry)| (key.clone(), entry.content.clone()))
+            .collect::<Vec<_>>();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.begin()?;
+        for (key, content) in &dirty {
+            let result = if let Some(content) = content {
+                self.inner.write(key, content)
+            } else {
+                self.inner.delete(key)
+            };
+            if let Err(err) = result {
+                self.inner.rollback()?;
+                return Err(err);
+            }
+        }
+        self.inner.commit()?;
+        self.inner.flush()?;
+
+        for (key, _) in dirty {
+            if let Some(entry) = state.entries.get_mut(&key) {
+                entry.dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn stage(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.published.is_some() {
+            return Err(anyhow::anyhow!("cache is already staging changes"));
+        }
+        state.published = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn publish(&self) {
+        self.state.write().unwrap().published = None;
+    }
+
+    fn read_published(&self, key: &str) -> Result<Option<String>> {
+        if let Some(published) = &self.state.read().unwrap().published {
+            if let Some(content) = published.get(key) {
+                return Ok(content.clone());
+            }
+        }
+        self.read(key)
+    }
+
+    fn list_published(&self, folder: &str) -> Vec<String> {
+        let prefix = super::folder_prefix(folder);
+        let mut result = self.list(folder).into_iter().collect::<BTreeSet<_>>();
+        if let Some(published) = &self.state.read().unwrap().published {
+            for (key, content) in published {
+                if !key.starts_with(&prefix) {
+                    continue;
+                }
+                if content.is_some() {
+                    result.insert(key.clone());
+                } else {
+                    result.remove(key);
+                }
+            }
+        }
+        result.into_iter().collect()
+    }
+
+    fn read_decoded(&self, key: &str) -> Option<Decoded> {
+        self.state
+            .read()
+            .unwrap()
+            .entries
+            .get(key)
+            .and_then(|o| o.decoded.clone())
+    }
+
+    fn store_decoded(&self, key: &str, value: Decoded) {
+        if let Some(entry) = self.state.write().unwrap().entries.get_mut(key) {
+            entry.decoded = Some(value);
+        }
+    }
+}
+
+#[test]
+fn write_behind_until_flush() {
+    use super::Memory;
+    let cache = Cache::new(Memory::default());
+    cache.write("market/Aromit.yaml", "a").unwrap();
+    assert_eq!(
+        cache.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("a")
+    );
+    assert_eq!(cache.inner.read("market/Aromit.yaml").unwrap(), None);
+    cache.flush().unwrap();
+    assert_eq!(
+        cache.inner.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("a")
+    );
+}
+
+#[test]
+fn list_includes_unflushed_changes() {
+    use super::Memory;
+    let cache = Cache::new(Memory::default());
+    cache.inner.write("market/Aromit.yaml", "a").unwrap();
+    cache.inner.write("market/Solmit.yaml", "b").unwrap();
+    cache.delete("market/Aromit.yaml").unwrap();
+    cache.write("market/Tormit.yaml", "c").unwrap();
+    assert_eq!(
+        cache.list("market"),
+        vec!["market/Solmit.yaml", "market/Tormit.yaml"]
+    );
+}
+
+#[test]
+fn rollback_restores_entries() {
+    use super::Memory;
+    let cache = Cache::new(Memory::default());
+    cache.write("market/Aromit.yaml", "before").unwrap();
+    cache.begin().unwrap();
+    cache.write("market/Aromit.yaml", "after").unwrap();
+    cache.write("market/Solmit.yaml", "new").unwrap();
+    cache.rollback().unwrap();
+    assert_eq!(
+        cache.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("before")
+    );
+    assert_eq!(cache.read("market/Solmit.yaml").unwrap(), None);
+}
+
+#[test]
+fn decoded_is_dropped_on_write() {
+    use super::Memory;
+    let cache = Cache::new(Memory::default());
+    cache.write("market/Aromit.yaml", "a").unwrap();
+    cache.store_decoded("market/Aromit.yaml", Arc::new(42_u32));
+    assert!(cache.read_decoded("market/Aromit.yaml").is_some());
+    cache.write("market/Aromit.yaml", "b").unwrap();
+    assert!(cache.read_decoded("market/Aromit.yaml").is_none());
+}
+
+#[test]
+fn published_view_hides_staged_changes() {
+    use super::Memory;
+    let cache = Cache::new(Memory::default());
+    cache.write("market/Aromit.yaml", "before").unwrap();
+    cache.stage().unwrap();
+    cache.write("market/Aromit.yaml", "after").unwrap();
+    cache.write("market/Solmit.yaml", "new").unwrap();
+    cache.flush().unwrap();
+    assert_eq!(
+        cache
+            .read_published("market/Aromit.yaml")
+            .unwrap()
+            .as_deref(),
+        Some("before")
+    );
+    assert_eq!(cache.read_published("market/Solmit.yaml").unwrap(), None);
+    assert_eq!(cache.list_published("market"), vec!["market/Aromit.yaml"]);
+    cache.publish();
+    assert_eq!(
+        cache
+            .read_published("market/Aromit.yaml")
+            .unwrap()
+            .as_deref(),
+        Some("after")
+    );
+    assert_eq!(cache.list_published("market").len(), 2);
+}
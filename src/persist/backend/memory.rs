@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use anyhow::Result;
+
+use super::Backend;
+
+/// Keeps everything in memory. Nothing survives a restart.
+///
+/// Useful for tests and simulations which should not touch the working directory.
+#[derive(Default)]
+pub struct Memory {
+    data: RwLock<BTreeMap<String, String>>,
+}
+
+impl Backend for Memory {
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.data.read().unwrap().get(key).cloned())
+    }
+
+    fn write(&self, key: &str, content: &str) -> Result<()> {
+        self.data
+            .write()
+            .unwrap()
+            .insert(key.to_string(), content.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.data.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, folder: &str) -> Vec<String> {
+        let prefix = super::folder_prefix(folder);
+        self.data
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|o| o.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn read_missing_is_none() {
+    let memory = Memory::default();
+    assert_eq!(memory.read("market/Aromit.yaml").unwrap(), None);
+}
+
+#[test]
+fn write_read_delete() {
+    let memory = Memory::default();
+    memory.write("market/Aromit.yaml", "buy: []").unwrap();
+    assert_eq!(
+        memory.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("buy: []")
+    );
+    memory.delete("market/Aromit.yaml").unwrap();
+    assert_eq!(memory.read("market/Aromit.yaml").unwrap(), None);
+}
+
+#[test]
+fn list_only_within_folder() {
+    let memory = Memory::default();
+    memory.write("sites/Vosu.yaml", "a").unwrap();
+    memory
+        .write("sites/entities/Vosu/Station0.yaml", "b")
+        .unwrap();
+    memory.write("sites-other/Vosu.yaml", "c").unwrap();
+    assert_eq!(
+        memory.list("sites/entities/"),
+        vec!["sites/entities/Vosu/Station0.yaml"]
+    );
+    assert_eq!(
+        memory.list("sites"),
+        vec!["sites/Vosu.yaml", "sites/entities/Vosu/Station0.yaml"]
+    );
+    assert_eq!(memory.list("").len(), 3);
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+
+mod cache;
+mod memory;
+mod published;
+mod sqlite;
+mod yaml_files;
+
+pub use self::cache::{Cache, Decoded};
+pub use self::memory::Memory;
+pub use self::published::Published;
+pub use self::sqlite::Sqlite;
+pub use self::yaml_files::YamlFiles;
+
+/// Prefix every key within the folder starts with.
+/// An empty folder contains every key.
+fn folder_prefix(folder: &str) -> String {
+    let folder = folder.trim_end_matches('/');
+    if folder.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", folder)
+    }
+}
+
+/// Where the persist structs keep their data.
+///
+/// Keys are relative paths like `market/Aromit.yaml`.
+/// The content is the serialized yaml of the value.
+pub trait Backend: Send + Sync {
+    /// Returns `None` when the key does not exist.
+    fn read(&self, key: &str) -> Result<Option<String>>;
+    fn write(&self, key: &str, content: &str) -> Result<()>;
+    /// Deleting a non existing key is not an error.
+    fn delete(&self, key: &str) -> Result<()>;
+    /// All keys within the folder and its subfolders.
+    fn list(&self, folder: &str) -> Vec<String>;
+
+    /// Start a transaction.
+    /// Every change until `commit` is applied at once or not at all.
+    /// Backends without transactions apply every change immediately.
+    fn begin(&self) -> Result<()> {
+        Ok(())
+    }
+    fn commit(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Discard every change since `begin`.
+    fn rollback(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write changes which are only kept in memory.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Keep showing the current state to readers of the published view until `publish`.
+    /// Backends without staging publish every change immediately.
+    fn stage(&self) -> Result<()> {
+        Ok(())
+    }
+    fn publish(&self) {}
+    fn read_published(&self, key: &str) -> Result<Option<String>> {
+        self.read(key)
+    }
+    fn list_published(&self, folder: &str) -> Vec<String> {
+        self.list(folder)
+    }
+
+    /// Already deserialized value of the key.
+    /// Backends which do not cache return `None`.
+    fn read_decoded(&self, _key: &str) -> Option<Decoded> {
+        None
+    }
+    /// Keep the deserialized value of the key which was just read or written.
+    fn store_decoded(&self, _key: &str, _value: Decoded) {}
+}
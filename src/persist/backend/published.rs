@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::Backend;
+
+/// Read only view of the published state of the inner backend.
+/// Changes staged by the gameloop stay hidden until they are published.
+pub struct Published<B: ?Sized> {
+    inner: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> Published<B> {
+    pub fn new(inner: Arc<B>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<B: Backend + ?Sized> Backend for Published<B> {
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        self.inner.read_published(key)
+    }
+
+    fn write(&self, key: &str, _content: &str) -> Result<()> {
+        Err(anyhow::anyhow!("published view is read only {:?}", key))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Err(anyhow::anyhow!("published view is read only {:?}", key))
+    }
+
+    fn list(&self, folder: &str) -> Vec<String> {
+        self.inner.list_published(folder)
+    }
+}
+
+#[test]
+fn writes_are_refused() {
+    use super::Memory;
+    let published = Published::new(Arc::new(Memory::default()));
+    assert!(published.write("market/Aromit.yaml", "a").is_err());
+    assert!(published.delete("market/Aromit.yaml").is_err());
+}
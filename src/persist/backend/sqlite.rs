@@ -0,0 +1,170 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::Backend;
+
+/// Every key is a row in an embedded `SQLite` database.
+///
+/// Transactions are savepoints so they can be nested.
+pub struct Sqlite {
+    connection: Mutex<Connection>,
+}
+
+impl Sqlite {
+    pub fn open<P: AsRef<Path>>(file: P) -> Result<Self> {
+        let file = file.as_ref();
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(file)
+            .map_err(|err| anyhow::anyhow!("failed to open sqlite {:?} {}", file, err))?;
+        Self::init(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self> {
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS persist (
+                key TEXT PRIMARY KEY NOT NULL,
+                content TEXT NOT NULL
+            ) WITHOUT ROWID;",
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl Backend for Sqlite {
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        let content = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT content FROM persist WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(content)
+    }
+
+    fn write(&self, key: &str, content: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO persist (key, content) VALUES (?1, ?2)
+            ON CONFLICT (key) DO UPDATE SET content = excluded.content",
+            params![key, content],
+        )?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM persist WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    fn list(&self, folder: &str) -> Vec<String> {
+        let prefix = super::folder_prefix(folder);
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare_cached(
+                "SELECT key FROM persist WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+            )
+            .expect("list statement should be valid sql");
+        let keys = statement
+            .query_map(params![prefix], |row| row.get(0))
+            .and_then(Iterator::collect);
+        keys.unwrap_or_else(|err| {
+            eprintln!("sqlite failed to list {} {}", folder, err);
+            Vec::new()
+        })
+    }
+
+    fn begin(&self) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute_batch("SAVEPOINT persist")?;
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute_batch("RELEASE persist")?;
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute_batch("ROLLBACK TO persist; RELEASE persist")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn write_read_list_delete() {
+    let sqlite = Sqlite::open_in_memory().unwrap();
+    sqlite.write("sites/Vosu.yaml", "a").unwrap();
+    sqlite
+        .write("sites/entities/Vosu/Station0.yaml", "b")
+        .unwrap();
+    sqlite
+        .write("sites/entities/Vosu/Station0.yaml", "c")
+        .unwrap();
+    sqlite.write("sites_other/Vosu.yaml", "d").unwrap();
+    assert_eq!(
+        sqlite
+            .read("sites/entities/Vosu/Station0.yaml")
+            .unwrap()
+            .as_deref(),
+        Some("c")
+    );
+    assert_eq!(
+        sqlite.list("sites"),
+        vec!["sites/Vosu.yaml", "sites/entities/Vosu/Station0.yaml"]
+    );
+    sqlite.delete("sites/Vosu.yaml").unwrap();
+    assert_eq!(sqlite.read("sites/Vosu.yaml").unwrap(), None);
+}
+
+#[test]
+fn rollback_discards_changes() {
+    let sqlite = Sqlite::open_in_memory().unwrap();
+    sqlite.write("market/Aromit.yaml", "before").unwrap();
+    sqlite.begin().unwrap();
+    sqlite.write("market/Aromit.yaml", "after").unwrap();
+    sqlite.write("market/Solmit.yaml", "new").unwrap();
+    sqlite.rollback().unwrap();
+    assert_eq!(
+        sqlite.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("before")
+    );
+    assert_eq!(sqlite.read("market/Solmit.yaml").unwrap(), None);
+}
+
+#[test]
+fn commit_keeps_changes() {
+    let sqlite = Sqlite::open_in_memory().unwrap();
+    sqlite.begin().unwrap();
+    sqlite.write("market/Aromit.yaml", "after").unwrap();
+    sqlite.commit().unwrap();
+    assert_eq!(
+        sqlite.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("after")
+    );
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::Backend;
+
+const QUARANTINE: &str = "quarantine";
+const TEMP_EXTENSION: &str = "tmp";
+
+/// Every key is a yaml file below the root directory.
+pub struct YamlFiles {
+    root: PathBuf,
+}
+
+impl YamlFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Move half-written and unparsable files into the quarantine folder.
+    /// Valid yaml not fitting its type is quarantined when it is read.
+    ///
+    /// Returns the moved files relative to the root.
+    pub fn recover(&self) -> Result<Vec<PathBuf>> {
+        let quarantine = self.root.join(QUARANTINE);
+        let mut moved = Vec::new();
+        for file in list(&self.root) {
+            let relative = file.strip_prefix(&self.root)?.to_path_buf();
+            if relative.starts_with(QUARANTINE) || is_intact(&file) {
+                continue;
+            }
+            let target = quarantine.join(&relative);
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::rename(&file, &target)
+                .map_err(|err| anyhow::anyhow!("failed to quarantine {:?} {}", file, err))?;
+            moved.push(relative);
+        }
+        Ok(moved)
+    }
+}
+
+impl Backend for YamlFiles {
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        let file = self.root.join(key);
+        match fs::read_to_string(&file) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(anyhow::anyhow!("failed to read {:?} {}", file, err)),
+        }
+    }
+
+    fn write(&self, key: &str, content: &str) -> Result<()> {
+        let file = self.root.join(key);
+        if fs::read_to_string(&file).map_or(true, |current| current != content) {
+            write_atomic(&file, content)?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let file = self.root.join(key);
+        if file.exists() {
+            fs::remove_file(file)?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, folder: &str) -> Vec<String> {
+        list(&self.root.join(folder))
+            .iter()
+            .filter(|o| !is_temp(o))
+            .filter_map(|o| o.strip_prefix(&self.root).ok())
+            .filter(|o| !o.starts_with(QUARANTINE))
+            .filter_map(Path::to_str)
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+/// Write into a temp file next to the target first and rename it into place afterwards.
+/// A crash mid-write only leaves the temp file behind, the target stays intact.
+fn write_atomic(file: &Path, content: &str) -> std::io::Result<()> {
+    let folder = file.parent().unwrap();
+    fs::create_dir_all(folder)?;
+
+    let mut temp_name = file.file_name().unwrap().to_os_string();
+    temp_name.push(".");
+    temp_name.push(TEMP_EXTENSION);
+    let temp = folder.join(temp_name);
+
+    {
+        let mut temp_file = fs::File::create(&temp)?;
+        temp_file.write_all(content.as_bytes())?;
+        temp_file.sync_all()?;
+    }
+    fs::rename(&temp, file)?;
+
+    // Persist the rename itself. Directories can not be opened on every platform.
+    if let Ok(folder) = fs::File::open(folder) {
+        folder.sync_all()?;
+    }
+    Ok(())
+}
+
+fn is_temp(file: &Path) -> bool {
+    file.extension() == Some(std::ffi::OsStr::new(TEMP_EXTENSION))
+}
+
+fn is_intact(file: &Path) -> bool {
+    if is_temp(file) {
+        return false;
+    }
+    match fs::read_to_string(file) {
+        Ok(content) => {
+            !content.trim().is_empty()
+                && serde_yaml::from_str::<serde_yaml::Value>(&content).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+fn list(folder: &Path) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    if let Ok(direntry) = fs::read_dir(folder) {
+        for entry in direntry.filter_map(std::result::Result::ok) {
+            if entry.path().is_dir() {
+                let mut children = list(&entry.path());
+                result.append(&mut children);
+            } else {
+                result.push(entry.path().clone());
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!(
+        "space-game-backend-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&root);
+    root
+}
+
+#[test]
+fn write_leaves_no_temp_file() {
+    let root = temp_root("write");
+    let files = YamlFiles::new(&root);
+    files.write("market/Aromit.yaml", "buy: []\n").unwrap();
+    assert_eq!(
+        files.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("buy: []\n")
+    );
+    assert_eq!(list(&root), vec![root.join("market/Aromit.yaml")]);
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn recover_quarantines_broken_files() {
+    let root = temp_root("recover");
+    let files = YamlFiles::new(&root);
+    files.write("market/Aromit.yaml", "buy: []\n").unwrap();
+    fs::write(root.join("market/Solmit.yaml"), "buy: [\n").unwrap();
+    fs::write(root.join("market/Tormit.yaml"), "").unwrap();
+    fs::write(root.join("market/Vesmit.yaml.tmp"), "buy").unwrap();
+
+    let mut moved = files.recover().unwrap();
+    moved.sort();
+    assert_eq!(
+        moved,
+        vec![
+            PathBuf::from("market/Solmit.yaml"),
+            PathBuf::from("market/Tormit.yaml"),
+            PathBuf::from("market/Vesmit.yaml.tmp"),
+        ]
+    );
+    assert_eq!(files.list("market"), vec!["market/Aromit.yaml"]);
+    assert!(root.join("quarantine/market/Solmit.yaml").exists());
+
+    // Nothing left to recover, already quarantined files stay where they are
+    assert!(files.recover().unwrap().is_empty());
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use super::Backend;
+
+const FILENAME: &str = "tick.yaml";
+
+/// In-game time counted in gameloop ticks.
+pub struct Clock<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> Clock<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+
+    /// The current tick. 0 before the first tick happened.
+    pub fn read(&self) -> u64 {
+        super::read(&*self.backend, FILENAME)
+    }
+
+    /// Start the next tick and return it.
+    pub fn advance(&mut self) -> anyhow::Result<u64> {
+        let tick = self.read().saturating_add(1);
+        super::write(&*self.backend, FILENAME, &tick)?;
+        Ok(tick)
+    }
+}
+
+#[test]
+fn advance_counts_up() {
+    let mut clock = Clock::new(Arc::new(super::Memory::default()));
+    assert_eq!(clock.read(), 0);
+    assert_eq!(clock.advance().unwrap(), 1);
+    assert_eq!(clock.advance().unwrap(), 2);
+    assert_eq!(clock.read(), 2);
+}
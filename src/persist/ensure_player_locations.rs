@@ -37,9 +37,8 @@ pub fn ensure_player_locations(statics: &Statics, persist: &mut Persist) -> anyh
                             "    player expected to be in site but site didnt knew: {:?} {} {:?}",
                             player, solarsystem, site
                         );
-                        persist
-                            .player_locations
-                            .write(player, PlayerLocation::default())?;
+                        let home = persist.player_generals.read_home_location(player);
+                        persist.player_locations.write(player, home)?;
                     }
                     true
                 } else {
@@ -53,17 +52,24 @@ pub fn ensure_player_locations(statics: &Statics, persist: &mut Persist) -> anyh
                 "    player expected to be in an non existing site. Bring player to existing site. {:?} was here: {:?}",
                 player, location
             );
-            let first_safe = all_sites
-                .iter()
-                .find(|o| o.0 == solarsystem && matches!(o.1, Site::Station(_) | Site::Stargate(_)))
-                .expect("system shouldve had at least a stargate");
-            persist.player_locations.write(
-                player,
-                PlayerLocation::Warp(PlayerLocationWarp {
-                    solarsystem,
-                    towards: first_safe.1,
-                }),
-            )?;
+            let first_safe = all_sites.iter().find(|o| {
+                o.0 == solarsystem && matches!(o.1, Site::Station(_) | Site::Stargate(_))
+            });
+            if let Some((_, towards)) = first_safe {
+                persist.player_locations.write(
+                    player,
+                    PlayerLocation::Warp(PlayerLocationWarp {
+                        solarsystem,
+                        towards: *towards,
+                    }),
+                )?;
+            } else {
+                // The sites of the solarsystem could not be read
+                eprintln!(
+                    "    no safe site known in {}. Leave player {:?} be.",
+                    solarsystem, player
+                );
+            }
         }
     }
 
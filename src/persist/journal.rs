@@ -0,0 +1,194 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use space_game_typings::fixed::item::Item;
+use space_game_typings::fixed::solarsystem::Solarsystem;
+use space_game_typings::market::{Order, Trade};
+use space_game_typings::player::Player;
+use space_game_typings::site::{Entity, Site};
+
+use crate::site::Instruction as SiteInstruction;
+use crate::spawn_tables::SpawnTables;
+use crate::station::Instruction as StationInstruction;
+
+/// Everything that changed the game state.
+///
+/// Replaying the events in order on an empty persist results in the same state.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    Tick,
+    SiteRound {
+        solarsystem: Solarsystem,
+        site: Site,
+        /// Instructions of every entity by its index in the site
+        instructions: Vec<(usize, Vec<SiteInstruction>)>,
+        /// Seed of the randomness within the round
+        #[serde(default)]
+        seed: u64,
+    },
+    StationInstructions {
+        player: Player,
+        instructions: Vec<StationInstruction>,
+    },
+    SiteAdded {
+        solarsystem: Solarsystem,
+        planet: u8,
+        site: Site,
+        entities: Vec<Entity>,
+    },
+    EntitiesSpawned {
+        solarsystem: Solarsystem,
+        site: Site,
+        entities: Vec<Entity>,
+    },
+    NpcBuyOrder {
+        item: Item,
+        order: Order,
+    },
+    SiteQuarantined {
+        solarsystem: Solarsystem,
+        site: Site,
+        reason: String,
+    },
+    Market {
+        trades: Vec<(Item, Trade)>,
+    },
+    /// Recorded on every start as the tables can change between runs
+    SpawnTables {
+        tables: SpawnTables,
+    },
+}
+
+/// Append-only log of every [`Event`] as json lines.
+///
+/// Events are kept in memory until the persist is flushed.
+/// Events of a rolled back transaction are never written.
+pub struct Journal {
+    file: Option<fs::File>,
+    pending: Vec<Event>,
+    transaction_start: Option<usize>,
+}
+
+impl Journal {
+    /// Events are not recorded. Used while replaying.
+    pub const fn disabled() -> Self {
+        Self {
+            file: None,
+            pending: Vec::new(),
+            transaction_start: None,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(file: P) -> Result<Self> {
+        let file = file.as_ref();
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .map_err(|err| anyhow::anyhow!("failed to open journal {:?} {}", file, err))?;
+        Ok(Self {
+            file: Some(file),
+            ..Self::disabled()
+        })
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if self.file.is_some() {
+            self.pending.push(event);
+        }
+    }
+
+    pub(super) fn begin(&mut self) {
+        self.transaction_start = Some(self.pending.len());
+    }
+
+    pub(super) fn commit(&mut self) {
+        self.transaction_start = None;
+    }
+
+    pub(super) fn rollback(&mut self) {
+        if let Some(start) = self.transaction_start.take() {
+            self.pending.truncate(start);
+        }
+    }
+
+    pub(super) fn flush(&mut self) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            if self.pending.is_empty() {
+                return Ok(());
+            }
+            let mut lines = Vec::new();
+            for event in &self.pending {
+                serde_json::to_writer(&mut lines, event)?;
+                lines.push(b'\n');
+            }
+            file.write_all(&lines)?;
+            file.sync_data()?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+pub fn read<P: AsRef<Path>>(file: P) -> Result<Vec<Event>> {
+    let file = file.as_ref();
+    let reader = BufReader::new(
+        fs::File::open(file)
+            .map_err(|err| anyhow::anyhow!("failed to open journal {:?} {}", file, err))?,
+    );
+    let mut events = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|err| {
+            anyhow::anyhow!(
+                "failed to parse journal {:?} line {} {}",
+                file,
+                index + 1,
+                err
+            )
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[test]
+fn rolled_back_events_are_not_written() {
+    let file = std::env::temp_dir().join(format!(
+        "space-game-backend-test-journal-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&file);
+
+    let mut journal = Journal::open(&file).unwrap();
+    journal.record(Event::Tick);
+    journal.begin();
+    journal.record(Event::Market { trades: vec![] });
+    journal.rollback();
+    journal.begin();
+    journal.record(Event::Tick);
+    journal.commit();
+    journal.flush().unwrap();
+
+    let events = read(&file).unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|o| matches!(o, Event::Tick)));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn disabled_records_nothing() {
+    let mut journal = Journal::disabled();
+    journal.record(Event::Tick);
+    assert!(journal.pending.is_empty());
+}
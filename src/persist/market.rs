@@ -1,28 +1,36 @@
+use std::sync::Arc;
+
 use space_game_typings::fixed::item::Item;
 use space_game_typings::market::{ItemMarket, Order, Trade};
 
-pub struct Market {}
+use super::Backend;
+
+pub struct Market<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> Market<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
 
-impl Market {
     fn filename(item: Item) -> String {
-        format!("persist/market/{}.yaml", item.to_string())
+        format!("market/{}.yaml", item.to_string())
     }
 
-    fn list(&self) -> Vec<Item> {
-        let list = super::list("persist/market");
-        list.iter()
-            .filter_map(|o| o.file_stem())
-            .filter_map(std::ffi::OsStr::to_str)
+    pub fn items(&self) -> Vec<Item> {
+        super::list_stems(&*self.backend, "market")
+            .iter()
             .filter_map(|o| o.parse::<Item>().ok())
             .collect()
     }
 
     fn read(&self, item: Item) -> ItemMarket {
-        super::read(Self::filename(item))
+        super::read(&*self.backend, &Self::filename(item))
     }
 
     fn write(&mut self, item: Item, market: &ItemMarket) -> anyhow::Result<()> {
-        super::write(Self::filename(item), market)
+        super::write(&*self.backend, &Self::filename(item), market)
     }
 
     pub fn get(&self, item: Item) -> ItemMarket {
@@ -49,16 +57,10 @@ impl Market {
         self.write(item, &market)
     }
 
-    pub fn trade(&mut self) -> anyhow::Result<Vec<(Item, Trade)>> {
-        let items = self.list();
-        let mut trades = Vec::new();
-        for item in items {
-            let mut market = self.read(item);
-            for t in market.resolve() {
-                trades.push((item, t));
-            }
-            self.write(item, &market)?;
-        }
+    pub fn trade(&mut self, item: Item) -> anyhow::Result<Vec<Trade>> {
+        let mut market = self.read(item);
+        let trades = market.resolve();
+        self.write(item, &market)?;
         Ok(trades)
     }
 }
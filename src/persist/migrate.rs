@@ -0,0 +1,307 @@
+use anyhow::Result;
+
+use space_game_typings::site::Site;
+
+use super::Backend;
+
+const VERSION_KEY: &str = "version.yaml";
+
+/// Version of the data layout written by this build.
+pub const CURRENT_VERSION: u32 = 4;
+
+/// Upgrades the persisted data from `to - 1` to `to`.
+pub struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    /// Returns the keys it changed.
+    pub run: fn(&dyn Backend) -> Result<Vec<String>>,
+}
+
+/// Add a migration here whenever a persisted type changes its shape.
+/// Migrations work on the raw yaml as the old shape can not be deserialized anymore.
+/// See [`map_yaml`].
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 2,
+        description: "group player notifications by tick",
+        run: notifications_by_tick,
+    },
+    Migration {
+        to: 3,
+        description: "warping entities know their arrival tick",
+        run: warping_with_arrival,
+    },
+    Migration {
+        to: 4,
+        description: "sites remember their planet",
+        run: planets_of_sites,
+    },
+];
+
+/// Upgrade old persist data to the [`CURRENT_VERSION`].
+///
+/// Fails on data from a newer version as this build does not understand it.
+pub fn migrate(backend: &dyn Backend) -> Result<()> {
+    migrate_with(backend, CURRENT_VERSION, MIGRATIONS)?;
+    backend.flush()
+}
+
+fn migrate_with(backend: &dyn Backend, current: u32, migrations: &[Migration]) -> Result<()> {
+    let mut version = read_version(backend, current)?;
+    if version > current {
+        return Err(anyhow::anyhow!(
+            "persist data has version {} but this build only knows up to version {}. Refuse to start.",
+            version,
+            current
+        ));
+    }
+
+    while version < current {
+        let migration = migrations
+            .iter()
+            .find(|o| o.to == version + 1)
+            .ok_or_else(|| anyhow::anyhow!("no migration to persist version {}", version + 1))?;
+        println!(
+            "    migrate persist to version {}: {}",
+            migration.to, migration.description
+        );
+        for key in (migration.run)(backend)? {
+            println!("      migrated {}", key);
+        }
+        version = migration.to;
+        write_version(backend, version)?;
+    }
+
+    Ok(())
+}
+
+/// Data written before the version marker existed is version 1.
+/// An empty persist starts at the current version right away.
+fn read_version(backend: &dyn Backend, current: u32) -> Result<u32> {
+    if let Some(content) = backend.read(VERSION_KEY)? {
+        let version = serde_yaml::from_str(&content)
+            .map_err(|err| anyhow::anyhow!("failed to parse persist version {}", err))?;
+        Ok(version)
+    } else if backend.list("").is_empty() {
+        write_version(backend, current)?;
+        Ok(current)
+    } else {
+        Ok(1)
+    }
+}
+
+fn write_version(backend: &dyn Backend, version: u32) -> Result<()> {
+    backend.write(VERSION_KEY, &serde_yaml::to_string(&version)?)
+}
+
+/// Change every yaml within the folder.
+/// `change` returns whether it changed the given value.
+///
+/// Returns the changed keys.
+pub fn map_yaml<F>(backend: &dyn Backend, folder: &str, change: F) -> Result<Vec<String>>
+where
+    F: Fn(&mut serde_yaml::Value) -> bool,
+{
+    let mut changed = Vec::new();
+    for key in backend.list(folder) {
+        if let Some(content) = backend.read(&key)? {
+            let mut value: serde_yaml::Value = serde_yaml::from_str(&content)
+                .map_err(|err| anyhow::anyhow!("failed to parse {:?} {}", key, err))?;
+            if change(&mut value) {
+                backend.write(&key, &serde_yaml::to_string(&value)?)?;
+                changed.push(key);
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Notifications from before the tick counter existed happened at tick 0.
+fn notifications_by_tick(backend: &dyn Backend) -> Result<Vec<String>> {
+    map_yaml(backend, "player-notifications", |value| {
+        if let serde_yaml::Value::Mapping(old) = value {
+            let mut grouped = serde_yaml::Mapping::new();
+            grouped.insert("tick".into(), 0.into());
+            for (key, value) in std::mem::take(old) {
+                grouped.insert(key, value);
+            }
+            *value = serde_yaml::Value::Sequence(vec![serde_yaml::Value::Mapping(grouped)]);
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// Entities warping before warps took time arrive right away.
+fn warping_with_arrival(backend: &dyn Backend) -> Result<Vec<String>> {
+    map_yaml(backend, "warping", |value| {
+        let mut changed = false;
+        if let Some(all) = value.as_sequence_mut() {
+            for warping in all {
+                if let Some([towards, entity]) = warping.as_sequence().map(Vec::as_slice) {
+                    let mut map = serde_yaml::Mapping::new();
+                    map.insert("towards".into(), towards.clone());
+                    map.insert("entity".into(), entity.clone());
+                    map.insert("departure".into(), 0.into());
+                    map.insert("arrival".into(), 0.into());
+                    *warping = serde_yaml::Value::Mapping(map);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    })
+}
+
+/// The sites of a solarsystem are grouped by planet.
+/// Keep the planet of every site on its own to look it up.
+fn planets_of_sites(backend: &dyn Backend) -> Result<Vec<String>> {
+    let mut changed = Vec::new();
+    for key in backend.list("sites") {
+        let solarsystem = match key
+            .strip_prefix("sites/")
+            .and_then(|o| o.strip_suffix(".yaml"))
+        {
+            Some(solarsystem) if !solarsystem.contains('/') => solarsystem.to_string(),
+            _ => continue,
+        };
+        let content = backend.read(&key)?.unwrap_or_default();
+        let value: serde_yaml::Value = serde_yaml::from_str(&content)
+            .map_err(|err| anyhow::anyhow!("failed to parse {:?} {}", key, err))?;
+        for (planet, sites) in value.as_mapping().into_iter().flatten() {
+            for site in sites.as_sequence().into_iter().flatten() {
+                let site: Site = serde_yaml::from_value(site.clone())
+                    .map_err(|err| anyhow::anyhow!("failed to parse site in {:?} {}", key, err))?;
+                let planet_key = format!("sites/planets/{}/{}.yaml", solarsystem, site);
+                backend.write(&planet_key, &serde_yaml::to_string(planet)?)?;
+                changed.push(planet_key);
+            }
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+fn rename_paperclips(backend: &dyn Backend) -> Result<Vec<String>> {
+    map_yaml(backend, "player-generals", |value| {
+        if let Some(map) = value.as_mapping_mut() {
+            if let Some(paperclips) = map.remove(&"clips".into()) {
+                map.insert("paperclips".into(), paperclips);
+                return true;
+            }
+        }
+        false
+    })
+}
+
+#[cfg(test)]
+const TEST_MIGRATIONS: &[Migration] = &[Migration {
+    to: 2,
+    description: "rename clips to paperclips",
+    run: rename_paperclips,
+}];
+
+#[test]
+fn empty_persist_starts_at_current() {
+    let backend = super::Memory::default();
+    migrate_with(&backend, 2, TEST_MIGRATIONS).unwrap();
+    assert_eq!(read_version(&backend, 2).unwrap(), 2);
+}
+
+#[test]
+fn unversioned_data_is_migrated() {
+    let backend = super::Memory::default();
+    backend
+        .write("player-generals/telegram-42.yaml", "clips: 5\n")
+        .unwrap();
+    backend
+        .write("player-generals/telegram-666.yaml", "paperclips: 7\n")
+        .unwrap();
+    migrate_with(&backend, 2, TEST_MIGRATIONS).unwrap();
+    assert_eq!(read_version(&backend, 2).unwrap(), 2);
+    let migrated: serde_yaml::Value = serde_yaml::from_str(
+        &backend
+            .read("player-generals/telegram-42.yaml")
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(migrated["paperclips"], serde_yaml::Value::from(5));
+    assert_eq!(
+        backend
+            .read("player-generals/telegram-666.yaml")
+            .unwrap()
+            .as_deref(),
+        Some("paperclips: 7\n")
+    );
+}
+
+#[test]
+fn future_version_is_refused() {
+    let backend = super::Memory::default();
+    write_version(&backend, 3).unwrap();
+    assert!(migrate_with(&backend, 2, TEST_MIGRATIONS).is_err());
+}
+
+#[test]
+fn missing_migration_is_an_error() {
+    let backend = super::Memory::default();
+    write_version(&backend, 1).unwrap();
+    assert!(migrate_with(&backend, 2, &[]).is_err());
+}
+
+#[test]
+fn notifications_are_grouped_at_tick_zero() {
+    let backend = super::Memory::default();
+    backend
+        .write("player-notifications/telegram-42.yaml", "trades: []\n")
+        .unwrap();
+    let changed = notifications_by_tick(&backend).unwrap();
+    assert_eq!(changed, ["player-notifications/telegram-42.yaml"]);
+    let migrated: serde_yaml::Value = serde_yaml::from_str(
+        &backend
+            .read("player-notifications/telegram-42.yaml")
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(migrated[0]["tick"], serde_yaml::Value::from(0));
+    assert!(migrated[0]["trades"].is_sequence());
+}
+
+#[test]
+fn warping_tuples_become_arrived() {
+    let backend = super::Memory::default();
+    backend
+        .write(
+            "warping/Vosu.yaml",
+            "- - Station: 0\n  - Facility: Station\n",
+        )
+        .unwrap();
+    let changed = warping_with_arrival(&backend).unwrap();
+    assert_eq!(changed, ["warping/Vosu.yaml"]);
+    let migrated: serde_yaml::Value =
+        serde_yaml::from_str(&backend.read("warping/Vosu.yaml").unwrap().unwrap()).unwrap();
+    assert_eq!(migrated[0]["arrival"], serde_yaml::Value::from(0));
+    assert!(migrated[0]["towards"].is_mapping());
+}
+
+#[test]
+fn planets_are_kept_per_site() {
+    let backend = super::Memory::default();
+    backend
+        .write(
+            "sites/Vosu.yaml",
+            "2:\n  - AsteroidField: 1\n  - Station: 0\n",
+        )
+        .unwrap();
+    backend
+        .write("sites/entities/Vosu/Station-0.yaml", "[]\n")
+        .unwrap();
+    let changed = planets_of_sites(&backend).unwrap();
+    assert_eq!(changed.len(), 2);
+    let key = format!("sites/planets/Vosu/{}.yaml", Site::AsteroidField(1));
+    let planet: u8 = serde_yaml::from_str(&backend.read(&key).unwrap().unwrap()).unwrap();
+    assert_eq!(planet, 2);
+}
@@ -1,117 +1,245 @@
 #![allow(clippy::module_name_repetitions, dead_code, clippy::unused_self)]
 
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 
+mod backend;
+mod clock;
 mod ensure_player_locations;
+pub mod journal;
 mod market;
+mod migrate;
 mod notifications;
 mod player;
+mod random;
 pub mod site;
+pub mod snapshot;
 
+#[allow(unused_imports)] // Used by tests and simulations
+pub use self::backend::Memory;
+pub use self::backend::{Backend, Cache, Published, Sqlite, YamlFiles};
+pub use self::clock::Clock;
 pub use self::ensure_player_locations::ensure_player_locations;
+pub use self::journal::Journal;
 pub use self::market::Market;
+pub use self::migrate::migrate;
 pub use self::notifications::Notifications;
+pub use self::player::PlayerInvulnerability;
 pub use self::player::PlayerLocations;
 pub use self::player::PlayerSiteInstructions;
+pub use self::player::{InsurancePolicy, InsuredShip, PlayerInsurances};
+pub use self::player::{KillRight, PlayerFlags};
 pub use self::player::{PlayerGenerals, PlayerStationAssets};
+pub use self::random::Random;
 pub use self::site::ensure_static_sites;
 pub use self::site::Sites;
 
-pub struct Persist {
-    pub market: Market,
-    pub player_generals: PlayerGenerals,
-    pub player_locations: PlayerLocations,
-    pub player_notifications: Notifications,
-    pub player_site_instructions: PlayerSiteInstructions,
-    pub player_station_assets: PlayerStationAssets,
-    pub sites: Sites,
+pub struct Persist<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+    pub clock: Clock<B>,
+    pub journal: Journal,
+    pub market: Market<B>,
+    pub player_flags: PlayerFlags<B>,
+    pub player_generals: PlayerGenerals<B>,
+    pub player_insurances: PlayerInsurances<B>,
+    pub player_invulnerability: PlayerInvulnerability<B>,
+    pub player_locations: PlayerLocations<B>,
+    pub player_notifications: Notifications<B>,
+    pub player_site_instructions: PlayerSiteInstructions<B>,
+    pub player_station_assets: PlayerStationAssets<B>,
+    pub random: Random<B>,
+    pub sites: Sites<B>,
 }
 
-impl Default for Persist {
-    fn default() -> Self {
+impl<B: Backend + ?Sized> Persist<B> {
+    pub fn new(backend: Arc<B>) -> Self {
         Self {
-            market: Market {},
-            player_generals: PlayerGenerals {},
-            player_locations: PlayerLocations {},
-            player_notifications: Notifications {},
-            player_site_instructions: PlayerSiteInstructions {},
-            player_station_assets: PlayerStationAssets {},
-            sites: Sites {},
+            clock: Clock::new(backend.clone()),
+            market: Market::new(backend.clone()),
+            player_flags: PlayerFlags::new(backend.clone()),
+            player_generals: PlayerGenerals::new(backend.clone()),
+            player_insurances: PlayerInsurances::new(backend.clone()),
+            player_invulnerability: PlayerInvulnerability::new(backend.clone()),
+            player_locations: PlayerLocations::new(backend.clone()),
+            player_notifications: Notifications::new(backend.clone()),
+            player_site_instructions: PlayerSiteInstructions::new(backend.clone()),
+            player_station_assets: PlayerStationAssets::new(backend.clone()),
+            random: Random::new(backend.clone()),
+            sites: Sites::new(backend.clone()),
+            backend,
+            journal: Journal::disabled(),
+        }
+    }
+
+    pub fn create_snapshot(&self, folder: &Path) -> anyhow::Result<std::path::PathBuf> {
+        self.backend.flush()?;
+        snapshot::create(&*self.backend, folder)
+    }
+
+    /// Write changes a caching backend only kept in memory until now.
+    /// Also writes the recorded journal events.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.backend.flush()?;
+        self.journal.flush()
+    }
+
+    /// Changes from now on stay hidden from the published view until `publish`.
+    pub fn stage(&self) -> anyhow::Result<()> {
+        self.backend.stage()
+    }
+
+    /// Show all the staged changes to the published view at once.
+    pub fn publish(&self) {
+        self.backend.publish();
+    }
+
+    /// Apply every change done by `action` at once or not at all.
+    /// When `action` fails its changes are rolled back.
+    pub fn transaction<T, F>(&mut self, action: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Self) -> anyhow::Result<T>,
+    {
+        self.backend.begin()?;
+        self.journal.begin();
+        match action(self) {
+            Ok(result) => {
+                self.backend.commit()?;
+                self.journal.commit();
+                Ok(result)
+            }
+            Err(err) => {
+                self.backend.rollback()?;
+                self.journal.rollback();
+                Err(err)
+            }
         }
     }
 }
 
-fn read<P: AsRef<Path>, T>(file: P) -> T
+impl Persist {
+    /// Read only persist which only sees published changes.
+    /// Lets requests read while the gameloop stages a tick.
+    pub fn published_view(&self) -> Self {
+        Self::new(Arc::new(Published::new(self.backend.clone())))
+    }
+}
+
+fn read<B: Backend + ?Sized, T>(backend: &B, key: &str) -> T
 where
-    T: serde::de::DeserializeOwned + Default,
+    T: serde::de::DeserializeOwned + Default + Clone + Send + Sync + 'static,
 {
-    let file = file.as_ref();
-    if let Ok(content) = fs::read_to_string(file) {
-        match serde_yaml::from_str(&content) {
-            Ok(result) => result,
-            Err(err) => panic!("failed to deserialize {:?} {}", file, err),
-        }
+    if let Some(value) = read_decoded(backend, key) {
+        return value.unwrap_or_default();
+    }
+    if let Ok(content) = backend.read(key) {
+        let value = content.and_then(|content| match serde_yaml::from_str(&content) {
+            Ok(result) => Some(result),
+            Err(err) => {
+                eprintln!("ERROR failed to deserialize {:?} {}", key, err);
+                quarantine(backend, key, &content);
+                None
+            }
+        });
+        backend.store_decoded(key, Arc::new(value.clone()));
+        value.unwrap_or_default()
     } else {
         T::default()
     }
 }
 
-fn read_meh<P: AsRef<Path>, T>(file: P) -> anyhow::Result<T>
+/// Move the content which does not fit its type out of the way so it reads as default from now on.
+/// Read only backends keep it where it is.
+fn quarantine<B: Backend + ?Sized>(backend: &B, key: &str, content: &str) {
+    let target = format!("quarantine/{}", key);
+    if backend.write(&target, content).is_ok() && backend.delete(key).is_ok() {
+        eprintln!("    quarantined broken persist key {:?}", key);
+    }
+}
+
+fn read_meh<B: Backend + ?Sized, T>(backend: &B, key: &str) -> anyhow::Result<T>
+where
+    T: serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    let value = if let Some(value) = read_decoded(backend, key) {
+        value
+    } else {
+        let content = backend
+            .read(key)
+            .map_err(|err| anyhow::anyhow!("failed to read {:?} {}", key, err))?;
+        let value = content
+            .map(|content| serde_yaml::from_str::<T>(&content))
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("failed to deserialize {:?} {}", key, err))?;
+        backend.store_decoded(key, Arc::new(value.clone()));
+        value
+    };
+    value.ok_or_else(|| anyhow::anyhow!("failed to read {:?} does not exist", key))
+}
+
+/// Caching backends keep the deserialized value as `Option<T>`.
+/// `None` means the key does not exist.
+#[allow(clippy::option_option)]
+fn read_decoded<B: Backend + ?Sized, T>(backend: &B, key: &str) -> Option<Option<T>>
 where
-    T: serde::de::DeserializeOwned,
+    T: Clone + 'static,
 {
-    let file = file.as_ref();
-    let content = fs::read_to_string(file)
-        .map_err(|err| anyhow::anyhow!("failed to read {:?} {}", file, err))?;
-    let value = serde_yaml::from_str(&content)
-        .map_err(|err| anyhow::anyhow!("failed to deserialize {:?} {}", file, err))?;
-    Ok(value)
+    backend
+        .read_decoded(key)?
+        .downcast_ref::<Option<T>>()
+        .cloned()
 }
 
-fn write<P: AsRef<Path>, T>(file: P, value: &T) -> anyhow::Result<()>
+fn write<B: Backend + ?Sized, T>(backend: &B, key: &str, value: &T) -> anyhow::Result<()>
 where
-    T: serde::Serialize + Default + std::cmp::PartialEq,
+    T: serde::Serialize + Default + std::cmp::PartialEq + Clone + Send + Sync + 'static,
 {
     if value == &T::default() {
-        delete(file)?;
+        backend.delete(key)?;
+        backend.store_decoded(key, Arc::new(None::<T>));
     } else {
-        let file = file.as_ref();
         let content = serde_yaml::to_string(value)
-            .map_err(|err| anyhow::anyhow!("failed to serialize {:?} {}", file, err))?;
-        write_str(file, &content)
-            .map_err(|err| anyhow::anyhow!("failed to write {:?} {}", file, err))?;
+            .map_err(|err| anyhow::anyhow!("failed to serialize {:?} {}", key, err))?;
+        backend
+            .write(key, &content)
+            .map_err(|err| anyhow::anyhow!("failed to write {:?} {}", key, err))?;
+        backend.store_decoded(key, Arc::new(Some(value.clone())));
     }
     Ok(())
 }
 
-fn write_str(file: &Path, new_content: &str) -> std::io::Result<()> {
-    if fs::read_to_string(file).map_or(true, |current| current != new_content) {
-        fs::create_dir_all(file.parent().unwrap())?;
-        fs::write(file, new_content)?;
-    }
-    Ok(())
+/// List the file stems of all keys within the folder.
+/// `player-location/Telegram-42.yaml` results in `Telegram-42`.
+fn list_stems<B: Backend + ?Sized>(backend: &B, folder: &str) -> Vec<String> {
+    backend
+        .list(folder)
+        .iter()
+        .filter_map(|o| Path::new(o).file_stem())
+        .filter_map(std::ffi::OsStr::to_str)
+        .map(ToString::to_string)
+        .collect()
 }
 
-fn delete<P: AsRef<Path>>(file: P) -> std::io::Result<()> {
-    let file = file.as_ref();
-    if file.exists() {
-        fs::remove_file(file)?;
+#[test]
+fn ensure_static_sites_in_memory() {
+    use crate::spawn_tables::SpawnTables;
+    use space_game_typings::fixed::Statics;
+    let statics = Statics::default();
+    let mut persist = Persist::new(Arc::new(Memory::default()));
+    ensure_static_sites(&statics, &SpawnTables::default(), &mut persist.sites).unwrap();
+    for solarsystem in statics.solarsystems.data.keys().copied() {
+        let sites = persist.sites.read_sites(solarsystem).unwrap();
+        assert!(!sites.all().is_empty());
     }
-    Ok(())
 }
 
-fn list<P: AsRef<Path>>(folder: P) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    if let Ok(direntry) = fs::read_dir(folder) {
-        for entry in direntry.filter_map(Result::ok) {
-            if entry.path().is_dir() {
-                let mut children = list(entry.path());
-                result.append(&mut children);
-            } else {
-                result.push(entry.path().clone());
-            }
-        }
-    }
-    result
+#[test]
+fn read_quarantines_content_of_another_type() {
+    let backend = Memory::default();
+    backend.write("clock.yaml", "tick: nope\n").unwrap();
+    assert_eq!(read::<_, u64>(&backend, "clock.yaml"), 0);
+    assert!(backend.read("clock.yaml").unwrap().is_none());
+    assert_eq!(
+        backend.read("quarantine/clock.yaml").unwrap().as_deref(),
+        Some("tick: nope\n")
+    );
 }
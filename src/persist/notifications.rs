@@ -1,40 +1,87 @@
+use std::sync::Arc;
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use space_game_typings::player::{self, Player};
 
-pub struct Notifications {}
+use super::Backend;
+
+/// Notifications which happened in the same tick.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TickNotifications {
+    pub tick: u64,
+    #[serde(flatten)]
+    pub notifications: player::Notifications,
+    /// Messages from the backend itself like a site being closed due to an error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+}
+
+pub struct Notifications<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> Notifications<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
 
-impl Notifications {
     fn filename(player: Player) -> String {
-        format!("persist/player-notifications/{}.yaml", player.to_string())
+        format!("player-notifications/{}.yaml", player.to_string())
     }
 
-    fn read(&self, player: Player) -> player::Notifications {
-        super::read(Self::filename(player))
+    fn read(&self, player: Player) -> Vec<TickNotifications> {
+        super::read(&*self.backend, &Self::filename(player))
     }
 
-    fn write(&mut self, player: Player, notifications: &player::Notifications) -> Result<()> {
-        super::write(Self::filename(player), notifications)
+    fn write(&mut self, player: Player, notifications: &[TickNotifications]) -> Result<()> {
+        super::write(
+            &*self.backend,
+            &Self::filename(player),
+            &notifications.to_vec(),
+        )
     }
 
-    pub fn add<N: Into<player::Notifications>>(&mut self, player: Player, add: N) -> Result<()> {
-        let mut add = add.into();
+    fn change_tick<F>(&mut self, player: Player, tick: u64, change: F) -> Result<()>
+    where
+        F: FnOnce(&mut TickNotifications),
+    {
         let mut current = self.read(player);
-        current.append(&mut add);
-        self.write(player, &current)?;
-        Ok(())
+        if current.last().map(|o| o.tick) != Some(tick) {
+            current.push(TickNotifications {
+                tick,
+                ..TickNotifications::default()
+            });
+        }
+        change(current.last_mut().expect("was ensured above"));
+        self.write(player, &current)
+    }
+
+    pub fn add<N: Into<player::Notifications>>(
+        &mut self,
+        player: Player,
+        tick: u64,
+        add: N,
+    ) -> Result<()> {
+        let mut add = add.into();
+        self.change_tick(player, tick, |current| {
+            current.notifications.append(&mut add);
+        })
+    }
+
+    pub fn add_message(&mut self, player: Player, tick: u64, message: String) -> Result<()> {
+        self.change_tick(player, tick, |current| current.messages.push(message))
     }
 
-    pub fn pop(&mut self, player: Player) -> Result<player::Notifications> {
+    pub fn pop(&mut self, player: Player) -> Result<Vec<TickNotifications>> {
         let result = self.read(player);
-        super::delete(Self::filename(player))?;
+        self.write(player, &[])?;
         Ok(result)
     }
 
     pub fn list_players(&self) -> Vec<Player> {
-        super::list("persist/player-notifications/")
+        super::list_stems(&*self.backend, "player-notifications")
             .iter()
-            .filter_map(|o| o.file_stem())
-            .filter_map(std::ffi::OsStr::to_str)
             .filter_map(|o| o.parse().ok())
             .collect()
     }
@@ -1,25 +1,107 @@
+use std::sync::Arc;
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use space_game_typings::fixed::solarsystem::Solarsystem;
-use space_game_typings::player::location::PlayerLocation;
+use space_game_typings::player::location::{PlayerLocation, PlayerLocationStation};
 use space_game_typings::player::{General, Player, StationAssets};
-use space_game_typings::site::instruction::{filter_possible, Instruction};
+use space_game_typings::ship::Ship;
+use space_game_typings::site::instruction::filter_possible;
+
+use crate::site::{self, Instruction};
+
+use super::{list_stems, read, write, Backend};
+
+/// `General` of the typings with the fields only the backend knows about.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct StoredGeneral {
+    #[serde(flatten)]
+    general: General,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    home: Option<PlayerLocationStation>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    security: i32,
+    /// Tick the security status was last lowered
+    #[serde(default, skip_serializing_if = "is_zero")]
+    security_tick: u64,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
 
-use super::{list, read, write};
+/// A lowered security status recovers by one every this many ticks up to 0.
+const SECURITY_RECOVERY_TICKS: u64 = 20;
 
-pub struct PlayerGenerals {}
-impl PlayerGenerals {
+pub struct PlayerGenerals<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerGenerals<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+    fn read_stored(&self, player: Player) -> StoredGeneral {
+        read(&*self.backend, &filename_player_generals(player))
+    }
+    fn write_stored(&mut self, player: Player, stored: &StoredGeneral) -> Result<()> {
+        write(&*self.backend, &filename_player_generals(player), stored)
+    }
     pub fn read(&self, player: Player) -> General {
-        super::read(&filename_player_generals(player))
+        self.read_stored(player).general
     }
     pub fn write(&mut self, player: Player, general: &General) -> Result<()> {
-        super::write(&filename_player_generals(player), general)
+        let mut stored = self.read_stored(player);
+        stored.general = general.clone();
+        self.write_stored(player, &stored)
+    }
+    /// Station the player respawns in when dying.
+    pub fn read_home(&self, player: Player) -> Option<PlayerLocationStation> {
+        self.read_stored(player).home
+    }
+    /// Home station of the player or the default location for players without one.
+    pub fn read_home_location(&self, player: Player) -> PlayerLocation {
+        self.read_home(player)
+            .map_or_else(PlayerLocation::default, PlayerLocation::Station)
+    }
+    pub fn write_home(
+        &mut self,
+        player: Player,
+        home: Option<PlayerLocationStation>,
+    ) -> Result<()> {
+        let mut stored = self.read_stored(player);
+        stored.home = home;
+        self.write_stored(player, &stored)
+    }
+    /// Security status of the player at the tick. Attacking others at guarded sites lowers it.
+    pub fn read_security(&self, player: Player, tick: u64) -> i32 {
+        let stored = self.read_stored(player);
+        if stored.security >= 0 {
+            return stored.security;
+        }
+        let recovered = tick.saturating_sub(stored.security_tick) / SECURITY_RECOVERY_TICKS;
+        let recovered = i32::try_from(recovered).unwrap_or(i32::MAX);
+        stored.security.saturating_add(recovered).min(0)
+    }
+    pub fn write_security(&mut self, player: Player, tick: u64, security: i32) -> Result<()> {
+        let mut stored = self.read_stored(player);
+        stored.security = security;
+        stored.security_tick = tick;
+        self.write_stored(player, &stored)
     }
 }
 
-pub struct PlayerStationAssets {}
-impl PlayerStationAssets {
+pub struct PlayerStationAssets<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerStationAssets<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
     pub fn read(&self, player: Player, solarsystem: Solarsystem, station: u8) -> StationAssets {
-        super::read(&filename_station_assets(player, solarsystem, station))
+        read(
+            &*self.backend,
+            &filename_station_assets(player, solarsystem, station),
+        )
     }
     pub fn write(
         &mut self,
@@ -28,26 +110,262 @@ impl PlayerStationAssets {
         station: u8,
         assets: &StationAssets,
     ) -> Result<()> {
-        super::write(
+        write(
+            &*self.backend,
             &filename_station_assets(player, solarsystem, station),
             assets,
         )
     }
 }
 
-pub struct PlayerLocations {}
-impl PlayerLocations {
+/// Consequences of attacking other players.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Flags {
+    /// Last tick the player is a criminal for attacking at a guarded site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub criminal_until: Option<u64>,
+    /// Last tick the player is a suspect for attacking at an unguarded site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspect_until: Option<u64>,
+    /// Players this player may attack without being flagged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kill_rights: Vec<KillRight>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KillRight {
+    pub against: Player,
+    /// Last tick the kill right can be used
+    pub until: u64,
+}
+
+impl Flags {
+    pub fn is_criminal(&self, tick: u64) -> bool {
+        matches!(self.criminal_until, Some(until) if tick <= until)
+    }
+    pub fn is_suspect(&self, tick: u64) -> bool {
+        matches!(self.suspect_until, Some(until) if tick <= until)
+    }
+    pub fn has_kill_right(&self, against: Player, tick: u64) -> bool {
+        self.kill_rights
+            .iter()
+            .any(|o| o.against == against && tick <= o.until)
+    }
+    /// Only the flags still active at the tick.
+    #[must_use]
+    pub fn active(mut self, tick: u64) -> Self {
+        self.criminal_until = self.criminal_until.filter(|until| tick <= *until);
+        self.suspect_until = self.suspect_until.filter(|until| tick <= *until);
+        self.kill_rights.retain(|o| tick <= o.until);
+        self
+    }
+}
+
+pub struct PlayerFlags<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerFlags<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+    pub fn read(&self, player: Player) -> Flags {
+        read(&*self.backend, &filename_flags(player))
+    }
+    /// Expired flags are dropped on write.
+    pub fn write(&mut self, player: Player, tick: u64, flags: Flags) -> Result<()> {
+        write(&*self.backend, &filename_flags(player), &flags.active(tick))
+    }
+}
+
+/// Paperclips paid out when the insured ship is destroyed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InsurancePolicy {
+    pub ship: InsuredShip,
+    pub payout: u64,
+}
+
+/// Where the insured ship is.
+/// Ships have no identity so the policy moves along with its ship.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InsuredShip {
+    /// The current ship of the player in the station
+    Current {
+        solarsystem: Solarsystem,
+        station: u8,
+    },
+    /// Stored in the station while the player uses another ship
+    Stored {
+        solarsystem: Solarsystem,
+        station: u8,
+        ship: Ship,
+    },
+    /// The ship the player flies in space
+    Undocked,
+}
+
+impl InsuredShip {
+    fn is_current(&self, solarsystem: Solarsystem, station: u8) -> bool {
+        self == &Self::Current {
+            solarsystem,
+            station,
+        }
+    }
+}
+
+pub struct PlayerInsurances<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerInsurances<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+    pub fn read(&self, player: Player) -> Vec<InsurancePolicy> {
+        read(&*self.backend, &filename_insurances(player))
+    }
+    fn write(&mut self, player: Player, all: &[InsurancePolicy]) -> Result<()> {
+        write(&*self.backend, &filename_insurances(player), &all.to_vec())
+    }
+    pub fn add(&mut self, player: Player, policy: InsurancePolicy) -> Result<()> {
+        let mut all = self.read(player);
+        all.push(policy);
+        self.write(player, &all)
+    }
+    /// Whether the current ship of the player in the station is insured.
+    pub fn is_current_insured(
+        &self,
+        player: Player,
+        solarsystem: Solarsystem,
+        station: u8,
+    ) -> bool {
+        self.read(player)
+            .iter()
+            .any(|o| o.ship.is_current(solarsystem, station))
+    }
+    /// The current ship switched places with a stored one.
+    pub fn switch(
+        &mut self,
+        player: Player,
+        solarsystem: Solarsystem,
+        station: u8,
+        before: Option<&Ship>,
+        after: Option<&Ship>,
+    ) -> Result<()> {
+        let mut all = self.read(player);
+        let becomes_current = all.iter().position(|o| {
+            matches!(&o.ship, InsuredShip::Stored { solarsystem: s, station: st, ship } if s == &solarsystem && st == &station && Some(ship) == after)
+        });
+        for (index, policy) in all.iter_mut().enumerate() {
+            if policy.ship.is_current(solarsystem, station) {
+                if let Some(ship) = before {
+                    policy.ship = InsuredShip::Stored {
+                        solarsystem,
+                        station,
+                        ship: ship.clone(),
+                    };
+                }
+            } else if Some(index) == becomes_current {
+                policy.ship = InsuredShip::Current {
+                    solarsystem,
+                    station,
+                };
+            }
+        }
+        self.write(player, &all)
+    }
+    /// The current ship of the station left into space.
+    pub fn undock(&mut self, player: Player, solarsystem: Solarsystem, station: u8) -> Result<()> {
+        let mut all = self.read(player);
+        // Only one ship of the player can be in space
+        all.retain(|o| o.ship != InsuredShip::Undocked);
+        for policy in &mut all {
+            if policy.ship.is_current(solarsystem, station) {
+                policy.ship = InsuredShip::Undocked;
+            }
+        }
+        self.write(player, &all)
+    }
+    /// The ship in space arrived in the station.
+    /// It is either the new current ship or stored next to the current one.
+    pub fn dock(
+        &mut self,
+        player: Player,
+        solarsystem: Solarsystem,
+        station: u8,
+        stored: Option<&Ship>,
+    ) -> Result<()> {
+        let mut all = self.read(player);
+        if stored.is_none() {
+            // The ship replaces the current one
+            all.retain(|o| !o.ship.is_current(solarsystem, station));
+        }
+        for policy in &mut all {
+            if policy.ship == InsuredShip::Undocked {
+                policy.ship = match stored {
+                    Some(ship) => InsuredShip::Stored {
+                        solarsystem,
+                        station,
+                        ship: ship.clone(),
+                    },
+                    None => InsuredShip::Current {
+                        solarsystem,
+                        station,
+                    },
+                };
+            }
+        }
+        self.write(player, &all)
+    }
+    /// Remove the policy of the ship destroyed in space and return its insured payout.
+    pub fn claim(&mut self, player: Player) -> Result<Option<u64>> {
+        let mut all = self.read(player);
+        if let Some(index) = all.iter().position(|o| o.ship == InsuredShip::Undocked) {
+            let policy = all.remove(index);
+            self.write(player, &all)?;
+            Ok(Some(policy.payout))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Players which can not be targeted until the given tick.
+/// Undocked ships are protected until the player acts.
+pub struct PlayerInvulnerability<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerInvulnerability<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+    /// Last tick the player is invulnerable.
+    pub fn read(&self, player: Player) -> Option<u64> {
+        read(&*self.backend, &filename_invulnerability(player))
+    }
+    pub fn write(&mut self, player: Player, until: Option<u64>) -> Result<()> {
+        write(&*self.backend, &filename_invulnerability(player), &until)
+    }
+    pub fn is_invulnerable(&self, player: Player, tick: u64) -> bool {
+        matches!(self.read(player), Some(until) if tick <= until)
+    }
+}
+
+pub struct PlayerLocations<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerLocations<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
     pub fn read(&self, player: Player) -> PlayerLocation {
-        super::read(&filename_player_location(player))
+        read(&*self.backend, &filename_player_location(player))
     }
     pub fn write(&mut self, player: Player, location: PlayerLocation) -> Result<()> {
-        super::write(&filename_player_location(player), &location)
+        write(&*self.backend, &filename_player_location(player), &location)
     }
     pub fn read_all_players(&self) -> Vec<Player> {
-        list("persist/player-location/")
+        list_stems(&*self.backend, "player-location")
             .iter()
-            .filter_map(|o| o.file_stem())
-            .filter_map(std::ffi::OsStr::to_str)
             .filter_map(|o| o.parse().ok())
             .collect()
     }
@@ -61,15 +379,23 @@ impl PlayerLocations {
     }
 }
 
-pub struct PlayerSiteInstructions {}
-impl PlayerSiteInstructions {
+pub struct PlayerSiteInstructions<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> PlayerSiteInstructions<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
     pub fn read(&self, player: Player) -> Vec<Instruction> {
-        let all: Vec<Instruction> = read(&filename_instructions(player));
-        filter_possible(&all)
+        let all: Vec<Instruction> = read(&*self.backend, &filename_instructions(player));
+        possible(&all)
     }
     pub fn write(&mut self, player: Player, instructions: &[Instruction]) -> Result<()> {
-        let possible = filter_possible(instructions);
-        write(&filename_instructions(player), &possible)
+        write(
+            &*self.backend,
+            &filename_instructions(player),
+            &possible(instructions),
+        )
     }
     pub fn add(&mut self, player: Player, instructions: &[Instruction]) -> Result<()> {
         let mut all = self.read(player);
@@ -80,23 +406,158 @@ impl PlayerSiteInstructions {
     }
 }
 
+/// The typings only filter their own instructions.
+/// The ones of the backend are kept after them. The ones of NPCs are dropped.
+fn possible(instructions: &[Instruction]) -> Vec<Instruction> {
+    filter_possible(&site::typings(instructions))
+        .into_iter()
+        .map(Instruction::Typings)
+        .chain(
+            instructions
+                .iter()
+                .filter(|o| matches!(o, Instruction::Backend(_)))
+                .copied(),
+        )
+        .collect()
+}
+
 fn filename_station_assets(player: Player, solarsystem: Solarsystem, station: u8) -> String {
     format!(
-        "persist/station-assets/{}/{}-{}.yaml",
+        "station-assets/{}/{}-{}.yaml",
         player.to_string(),
         solarsystem,
         station
     )
 }
 fn filename_player_generals(player: Player) -> String {
-    format!("persist/player-generals/{}.yaml", player.to_string())
+    format!("player-generals/{}.yaml", player.to_string())
+}
+fn filename_flags(player: Player) -> String {
+    format!("player-flags/{}.yaml", player.to_string())
+}
+fn filename_insurances(player: Player) -> String {
+    format!("player-insurances/{}.yaml", player.to_string())
+}
+fn filename_invulnerability(player: Player) -> String {
+    format!("player-invulnerable/{}.yaml", player.to_string())
 }
 fn filename_player_location(player: Player) -> String {
-    format!("persist/player-location/{}.yaml", player.to_string())
+    format!("player-location/{}.yaml", player.to_string())
 }
 fn filename_instructions(player: Player) -> String {
-    format!("persist/player-instructions/{}.yaml", player.to_string())
+    format!("player-instructions/{}.yaml", player.to_string())
 }
 fn filename_site_log(player: Player) -> String {
-    format!("persist/player-sitelog/{}.yaml", player.to_string())
+    format!("player-sitelog/{}.yaml", player.to_string())
+}
+
+#[test]
+fn home_and_general_dont_overwrite_each_other() {
+    let mut generals = PlayerGenerals::new(Arc::new(super::Memory::default()));
+    let player = Player::Telegram(42);
+    let home = PlayerLocationStation {
+        solarsystem: Solarsystem::Vosu,
+        station: 1,
+    };
+    generals.write_home(player, Some(home)).unwrap();
+    let mut general = generals.read(player);
+    general.paperclips = 5;
+    generals.write(player, &general).unwrap();
+    assert_eq!(generals.read_home(player), Some(home));
+    assert_eq!(generals.read(player).paperclips, 5);
+}
+
+#[test]
+fn security_recovers_up_to_zero() {
+    let mut generals = PlayerGenerals::new(Arc::new(super::Memory::default()));
+    let player = Player::Telegram(42);
+    generals.write_security(player, 100, -3).unwrap();
+    assert_eq!(generals.read_security(player, 100), -3);
+    assert_eq!(
+        generals.read_security(player, 100 + SECURITY_RECOVERY_TICKS - 1),
+        -3
+    );
+    assert_eq!(
+        generals.read_security(player, 100 + SECURITY_RECOVERY_TICKS),
+        -2
+    );
+    assert_eq!(
+        generals.read_security(player, 100 + SECURITY_RECOVERY_TICKS * 10),
+        0
+    );
+}
+
+#[test]
+fn expired_flags_are_dropped() {
+    let mut all = PlayerFlags::new(Arc::new(super::Memory::default()));
+    let player = Player::Telegram(42);
+    let victim = Player::Telegram(666);
+    all.write(
+        player,
+        5,
+        Flags {
+            criminal_until: Some(10),
+            suspect_until: Some(4),
+            kill_rights: vec![KillRight {
+                against: victim,
+                until: 20,
+            }],
+        },
+    )
+    .unwrap();
+    let flags = all.read(player);
+    assert!(flags.is_criminal(10));
+    assert!(!flags.is_criminal(11));
+    assert_eq!(flags.suspect_until, None);
+    assert!(flags.has_kill_right(victim, 20));
+    assert!(!flags.has_kill_right(player, 20));
+}
+
+#[test]
+fn insurance_follows_the_ship() {
+    let mut insurances = PlayerInsurances::new(Arc::new(super::Memory::default()));
+    let player = Player::Telegram(42);
+    let solarsystem = Solarsystem::Vosu;
+    let insured = Ship::default();
+    let other = Ship {
+        collateral: space_game_typings::ship::Collateral {
+            capacitor: 1,
+            armor: 2,
+            structure: 3,
+        },
+        ..Ship::default()
+    };
+    insurances
+        .add(
+            player,
+            InsurancePolicy {
+                ship: InsuredShip::Current {
+                    solarsystem,
+                    station: 1,
+                },
+                payout: 100,
+            },
+        )
+        .unwrap();
+    assert!(insurances.is_current_insured(player, solarsystem, 1));
+
+    // Flying another ship does not claim the stored insured one
+    insurances
+        .switch(player, solarsystem, 1, Some(&insured), Some(&other))
+        .unwrap();
+    assert!(!insurances.is_current_insured(player, solarsystem, 1));
+    insurances.undock(player, solarsystem, 1).unwrap();
+    assert_eq!(insurances.claim(player).unwrap(), None);
+
+    insurances
+        .switch(player, solarsystem, 1, None, Some(&insured))
+        .unwrap();
+    assert!(insurances.is_current_insured(player, solarsystem, 1));
+    insurances.undock(player, solarsystem, 1).unwrap();
+    insurances.dock(player, solarsystem, 2, None).unwrap();
+    assert!(insurances.is_current_insured(player, solarsystem, 2));
+    insurances.undock(player, solarsystem, 2).unwrap();
+    assert_eq!(insurances.claim(player).unwrap(), Some(100));
+    assert_eq!(insurances.claim(player).unwrap(), None);
+    assert!(insurances.read(player).is_empty());
 }
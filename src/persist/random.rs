@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::Backend;
+
+const FILENAME: &str = "random-seed.yaml";
+
+/// Seed of the gameloop randomness.
+/// The same seed with the same inputs results in the same universe.
+pub struct Random<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> Random<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
+
+    pub fn read_seed(&self) -> Option<u64> {
+        super::read(&*self.backend, FILENAME)
+    }
+
+    pub fn write_seed(&mut self, seed: u64) -> anyhow::Result<()> {
+        super::write(&*self.backend, FILENAME, &Some(seed))
+    }
+
+    /// Random number generator for the next gameloop tick.
+    /// Its successor seed is persisted right away so every tick continues where the last one stopped.
+    /// A random seed is used when none was persisted yet.
+    pub fn next_rng(&mut self) -> anyhow::Result<StdRng> {
+        let seed = self.read_seed().unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.write_seed(rng.gen())?;
+        Ok(rng)
+    }
+}
+
+#[test]
+fn same_seed_same_numbers() {
+    use super::Memory;
+    let mut first = Random::new(Arc::new(Memory::default()));
+    let mut second = Random::new(Arc::new(Memory::default()));
+    first.write_seed(42).unwrap();
+    second.write_seed(42).unwrap();
+    for _ in 0..3 {
+        let a = first.next_rng().unwrap().gen::<u64>();
+        let b = second.next_rng().unwrap().gen::<u64>();
+        assert_eq!(a, b);
+    }
+    assert_eq!(first.read_seed(), second.read_seed());
+}
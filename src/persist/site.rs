@@ -1,25 +1,85 @@
+use std::sync::Arc;
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use space_game_typings::fixed::facility::Facility;
-use space_game_typings::fixed::module::Targeted;
-use space_game_typings::fixed::npc_faction::NpcFaction;
-use space_game_typings::fixed::shiplayout::ShipLayout;
 use space_game_typings::fixed::solarsystem::Solarsystem;
 use space_game_typings::fixed::{Solarsystems, Statics};
-use space_game_typings::ship::{Fitting, Ship};
-use space_game_typings::site::{Entity, Site, SitesNearPlanet};
+use space_game_typings::site::{Entity, Site, SiteLogActor, SitesNearPlanet};
+use space_game_typings::storage::Storage;
+
+use super::{read, read_meh, write, Backend};
+use crate::spawn_tables::SpawnTables;
+
+/// A site which failed to be handled by the gameloop.
+/// Its entities are kept for inspection but are not part of the game anymore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantinedSite {
+    pub solarsystem: Solarsystem,
+    pub site: Site,
+    pub tick: u64,
+    pub reason: String,
+}
+
+/// An entity on its way to a site within the solarsystem.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Warping {
+    pub towards: Site,
+    pub entity: Entity,
+    /// Tick the warp started
+    pub departure: u64,
+    /// First tick the entity is part of the target site
+    pub arrival: u64,
+}
+
+impl Warping {
+    /// Share of the way already done between 0 and 1.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn progress(&self, tick: u64) -> f32 {
+        let total = self.arrival.saturating_sub(self.departure);
+        let done = tick.saturating_sub(self.departure).min(total);
+        if total == 0 {
+            1.0
+        } else {
+            done as f32 / total as f32
+        }
+    }
+}
+
+/// Remains of a destroyed ship which can be looted until it expires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Wreck {
+    /// The destroyed one
+    pub actor: SiteLogActor,
+    pub storage: Storage,
+    /// Last tick the wreck is part of the site
+    pub expires: u64,
+}
 
-use super::{delete, read, read_meh, write};
+/// Pirates of an asteroid field.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PirateActivity {
+    /// Waves cleared by players in a row
+    pub wave: u8,
+    /// Rounds the pirates spent without players around
+    pub idle: u64,
+}
 
-pub struct Sites {}
-impl Sites {
+pub struct Sites<B: ?Sized = dyn Backend> {
+    backend: Arc<B>,
+}
+impl<B: Backend + ?Sized> Sites<B> {
+    pub fn new(backend: Arc<B>) -> Self {
+        Self { backend }
+    }
     pub fn read_sites(&self, solarsystem: Solarsystem) -> Result<SitesNearPlanet> {
-        read_meh(&filename_sites(solarsystem))
+        read_meh(&*self.backend, &filename_sites(solarsystem))
     }
     fn write_sites(&mut self, solarsystem: Solarsystem, sites: &SitesNearPlanet) -> Result<()> {
-        write(&filename_sites(solarsystem), sites)
+        write(&*self.backend, &filename_sites(solarsystem), sites)
     }
     pub fn read_entities(&self, solarsystem: Solarsystem, site: Site) -> Result<Vec<Entity>> {
-        read_meh(&filename_site_entities(solarsystem, site))
+        read_meh(&*self.backend, &filename_site_entities(solarsystem, site))
     }
     pub fn write_entities(
         &mut self,
@@ -34,14 +94,23 @@ impl Sites {
                 site
             ));
         }
-        write(&filename_site_entities(solarsystem, site), &entities)
+        write(
+            &*self.backend,
+            &filename_site_entities(solarsystem, site),
+            &entities.to_vec(),
+        )
     }
+    /// Solarsystems whose sites can not be read are left out so the others keep running.
     pub fn read_sites_everywhere(&self, solarsystems: &Solarsystems) -> Vec<(Solarsystem, Site)> {
         let mut result = Vec::new();
         for solarsystem in solarsystems.data.keys().copied() {
-            let sites = self
-                .read_sites(solarsystem)
-                .expect("init at least created gate sites");
+            let sites = match self.read_sites(solarsystem) {
+                Ok(sites) => sites,
+                Err(err) => {
+                    eprintln!("ERROR persist::sites {} {}", solarsystem, err);
+                    continue;
+                }
+            };
             for site in sites.all() {
                 result.push((solarsystem, site));
             }
@@ -56,6 +125,7 @@ impl Sites {
         entities: &[Entity],
     ) -> Result<()> {
         self.write_entities(solarsystem, site, entities)?;
+        self.write_planet(solarsystem, site, Some(planet))?;
 
         let mut sites = self.read_sites(solarsystem)?;
         sites.add(planet, site);
@@ -65,49 +135,231 @@ impl Sites {
         let mut sites = self.read_sites(solarsystem)?;
         sites.remove(site);
         self.write_sites(solarsystem, &sites)?;
-        delete(&filename_site_entities(solarsystem, site))?;
+        for filename in [
+            filename_site_entities(solarsystem, site),
+            filename_wrecks(solarsystem, site),
+            filename_pirates(solarsystem, site),
+            filename_depleted(solarsystem, site),
+            filename_planet(solarsystem, site),
+        ] {
+            self.backend.delete(&filename)?;
+        }
         Ok(())
     }
+
+    /// Rounds the asteroid field spent without any asteroid.
+    pub fn read_depleted(&self, solarsystem: Solarsystem, site: Site) -> u64 {
+        read(&*self.backend, &filename_depleted(solarsystem, site))
+    }
+    pub fn write_depleted(
+        &mut self,
+        solarsystem: Solarsystem,
+        site: Site,
+        rounds: u64,
+    ) -> Result<()> {
+        write(
+            &*self.backend,
+            &filename_depleted(solarsystem, site),
+            &rounds,
+        )
+    }
+
+    pub fn read_pirates(&self, solarsystem: Solarsystem, site: Site) -> PirateActivity {
+        read(&*self.backend, &filename_pirates(solarsystem, site))
+    }
+    pub fn write_pirates(
+        &mut self,
+        solarsystem: Solarsystem,
+        site: Site,
+        activity: &PirateActivity,
+    ) -> Result<()> {
+        write(
+            &*self.backend,
+            &filename_pirates(solarsystem, site),
+            activity,
+        )
+    }
+
+    pub fn read_wrecks(&self, solarsystem: Solarsystem, site: Site) -> Vec<Wreck> {
+        read(&*self.backend, &filename_wrecks(solarsystem, site))
+    }
+    pub fn write_wrecks(
+        &mut self,
+        solarsystem: Solarsystem,
+        site: Site,
+        wrecks: &[Wreck],
+    ) -> Result<()> {
+        write(
+            &*self.backend,
+            &filename_wrecks(solarsystem, site),
+            &wrecks.to_vec(),
+        )
+    }
+
+    /// Remove the site from the game while keeping its raw entities file.
+    pub fn quarantine_site(&mut self, quarantined: QuarantinedSite) -> Result<()> {
+        let solarsystem = quarantined.solarsystem;
+        let site = quarantined.site;
+
+        let mut sites = self.read_sites(solarsystem)?;
+        sites.remove(site);
+        self.write_sites(solarsystem, &sites)?;
+
+        let entities = filename_site_entities(solarsystem, site);
+        if let Some(content) = self.backend.read(&entities)? {
+            self.backend
+                .write(&filename_quarantine_entities(solarsystem, site), &content)?;
+        }
+        self.backend.delete(&entities)?;
+
+        let mut all = self.read_quarantined();
+        all.push(quarantined);
+        write(&*self.backend, FILENAME_QUARANTINE, &all)
+    }
+    pub fn read_quarantined(&self) -> Vec<QuarantinedSite> {
+        read(&*self.backend, FILENAME_QUARANTINE)
+    }
+
+    pub fn read_entitiy_warping(&self, solarsystem: Solarsystem) -> Vec<Warping> {
+        read(&*self.backend, &filename_warping(solarsystem))
+    }
+    /// Entities which arrived at the site until the given tick.
+    pub fn read_entity_arrived(
+        &self,
+        solarsystem: Solarsystem,
+        site: Site,
+        tick: u64,
+    ) -> Vec<Entity> {
+        self.read_entitiy_warping(solarsystem)
+            .into_iter()
+            .filter(|o| o.towards == site && o.arrival <= tick)
+            .map(|o| o.entity)
+            .collect()
+    }
+    /// Remove the entities which arrived at the site until the given tick.
+    pub fn pop_entity_arrived(
+        &mut self,
+        solarsystem: Solarsystem,
+        site: Site,
+        tick: u64,
+    ) -> Result<Vec<Entity>> {
+        let mut other = Vec::new();
+        let mut result = Vec::new();
+        for warping in self.read_entitiy_warping(solarsystem) {
+            if warping.towards == site && warping.arrival <= tick {
+                result.push(warping.entity);
+            } else {
+                other.push(warping);
+            }
+        }
+        write(&*self.backend, &filename_warping(solarsystem), &other)?;
+        Ok(result)
+    }
+    /// Remove the entities warping towards sites which are not part of the solarsystem anymore.
+    pub fn pop_entity_orphaned(
+        &mut self,
+        solarsystem: Solarsystem,
+        existing: &[Site],
+    ) -> Result<Vec<Entity>> {
+        let (orphaned, other): (Vec<_>, Vec<_>) = self
+            .read_entitiy_warping(solarsystem)
+            .into_iter()
+            .partition(|o| !existing.contains(&o.towards));
+        if !orphaned.is_empty() {
+            write(&*self.backend, &filename_warping(solarsystem), &other)?;
+        }
+        Ok(orphaned.into_iter().map(|o| o.entity).collect())
+    }
+    pub fn add_entity_warping(&mut self, solarsystem: Solarsystem, warping: Warping) -> Result<()> {
+        let mut current = self.read_entitiy_warping(solarsystem);
+        current.push(warping);
+        write(&*self.backend, &filename_warping(solarsystem), &current)
+    }
+
+    /// Entities undocking into the station site with the next round.
+    pub fn read_entity_undocking(&self, solarsystem: Solarsystem, station: u8) -> Vec<Entity> {
+        let all: Vec<(u8, Entity)> = read(&*self.backend, &filename_undocking(solarsystem));
+        all.into_iter()
+            .filter(|(s, _)| *s == station)
+            .map(|(_, entity)| entity)
+            .collect()
+    }
+    pub fn pop_entity_undocking(
+        &mut self,
+        solarsystem: Solarsystem,
+        station: u8,
+    ) -> Result<Vec<Entity>> {
+        let all: Vec<(u8, Entity)> = read(&*self.backend, &filename_undocking(solarsystem));
+        let (result, other): (Vec<_>, Vec<_>) = all.into_iter().partition(|(s, _)| *s == station);
+        write(&*self.backend, &filename_undocking(solarsystem), &other)?;
+        Ok(result.into_iter().map(|(_, entity)| entity).collect())
+    }
+    pub fn add_entity_undocking(
+        &mut self,
+        solarsystem: Solarsystem,
+        station: u8,
+        entity: Entity,
+    ) -> Result<()> {
+        let mut all: Vec<(u8, Entity)> = read(&*self.backend, &filename_undocking(solarsystem));
+        all.push((station, entity));
+        write(&*self.backend, &filename_undocking(solarsystem), &all)
+    }
+
+    /// Planet the site is near to.
+    /// The typings only expose all sites at once so it is kept next to them.
+    pub fn read_planet(&self, solarsystem: Solarsystem, site: Site) -> Option<u8> {
+        read(&*self.backend, &filename_planet(solarsystem, site))
+    }
+    fn write_planet(
+        &mut self,
+        solarsystem: Solarsystem,
+        site: Site,
+        planet: Option<u8>,
+    ) -> Result<()> {
+        write(&*self.backend, &filename_planet(solarsystem, site), &planet)
+    }
 }
 
 fn filename_site_entities(solarsystem: Solarsystem, site: Site) -> String {
-    format!(
-        "persist/sites/entities/{}/{}.yaml",
-        solarsystem,
-        site.to_string()
-    )
+    format!("sites/entities/{}/{}.yaml", solarsystem, site.to_string())
+}
+fn filename_wrecks(solarsystem: Solarsystem, site: Site) -> String {
+    format!("sites/wrecks/{}/{}.yaml", solarsystem, site.to_string())
+}
+fn filename_depleted(solarsystem: Solarsystem, site: Site) -> String {
+    format!("sites/depleted/{}/{}.yaml", solarsystem, site.to_string())
+}
+fn filename_planet(solarsystem: Solarsystem, site: Site) -> String {
+    format!("sites/planets/{}/{}.yaml", solarsystem, site.to_string())
+}
+fn filename_pirates(solarsystem: Solarsystem, site: Site) -> String {
+    format!("sites/pirates/{}/{}.yaml", solarsystem, site.to_string())
 }
 fn filename_sites(solarsystem: Solarsystem) -> String {
-    format!("persist/sites/{}.yaml", solarsystem)
+    format!("sites/{}.yaml", solarsystem)
 }
 fn filename_warping(solarsystem: Solarsystem) -> String {
-    format!("persist/warping/{}.yaml", solarsystem)
+    format!("warping/{}.yaml", solarsystem)
 }
-
-pub fn read_entitiy_warping(solarsystem: Solarsystem) -> Vec<(Site, Entity)> {
-    read(&filename_warping(solarsystem))
-}
-pub fn pop_entity_warping(solarsystem: Solarsystem, site: Site) -> Result<Vec<Entity>> {
-    let mut other = Vec::new();
-    let mut result = Vec::new();
-    for (towards, entity) in read_entitiy_warping(solarsystem) {
-        if site == towards {
-            result.push(entity);
-        } else {
-            other.push((towards, entity));
-        }
-    }
-    write(filename_warping(solarsystem), &other)?;
-    Ok(result)
+fn filename_undocking(solarsystem: Solarsystem) -> String {
+    format!("undocking/{}.yaml", solarsystem)
 }
-pub fn add_entity_warping(solarsystem: Solarsystem, target: Site, entity: Entity) -> Result<()> {
-    let mut current = read_entitiy_warping(solarsystem);
-    current.push((target, entity));
-    write(&filename_warping(solarsystem), &current)
+const FILENAME_QUARANTINE: &str = "sites/quarantine/sites.yaml";
+fn filename_quarantine_entities(solarsystem: Solarsystem, site: Site) -> String {
+    format!(
+        "sites/quarantine/site-entities/{}/{}.yaml",
+        solarsystem,
+        site.to_string()
+    )
 }
 
-pub fn ensure_static_sites(statics: &Statics, sites: &mut Sites) -> Result<()> {
+pub fn ensure_static_sites<B: Backend + ?Sized>(
+    statics: &Statics,
+    spawn_tables: &SpawnTables,
+    sites: &mut Sites<B>,
+) -> Result<()> {
     for (solarsystem, data) in &statics.solarsystems.data {
+        let guards = spawn_tables.get(statics, *solarsystem).guards(statics);
         let mut system_sites = sites.read_sites(*solarsystem).unwrap_or_default();
 
         // Purge stations and stargates from overview.
@@ -131,10 +383,11 @@ pub fn ensure_static_sites(statics: &Statics, sites: &mut Sites) -> Result<()> {
                 .cloned()
                 .collect::<Vec<_>>();
             // Add guards
-            add_guards(statics, &mut entities);
+            entities.splice(0..0, guards.iter().cloned());
             // Add stargate
             entities.insert(0, Entity::Facility(Facility::Stargate));
             sites.write_entities(*solarsystem, site, &entities)?;
+            sites.write_planet(*solarsystem, site, Some(*planet))?;
 
             system_sites.add(*planet, site);
         }
@@ -153,10 +406,11 @@ pub fn ensure_static_sites(statics: &Statics, sites: &mut Sites) -> Result<()> {
                 .cloned()
                 .collect::<Vec<_>>();
             // Add guards
-            add_guards(statics, &mut entities);
+            entities.splice(0..0, guards.iter().cloned());
             // Add station
             entities.insert(0, Entity::Facility(Facility::Station));
             sites.write_entities(*solarsystem, site, &entities)?;
+            sites.write_planet(*solarsystem, site, Some(planet))?;
 
             system_sites.add(planet, site);
         }
@@ -166,24 +420,42 @@ pub fn ensure_static_sites(statics: &Statics, sites: &mut Sites) -> Result<()> {
     Ok(())
 }
 
-fn add_guards(statics: &Statics, entities: &mut Vec<Entity>) {
-    for _ in 0..3 {
-        let fitting = Fitting {
-            layout: ShipLayout::Paladin,
-            slots_passive: vec![],
-            slots_targeted: vec![
-                Targeted::GuardianLaser,
-                Targeted::GuardianLaser,
-                Targeted::GuardianLaser,
-                Targeted::GuardianLaser,
-                Targeted::GuardianLaser,
-                Targeted::GuardianLaser,
-            ],
-            slots_untargeted: vec![],
-        };
-        entities.insert(
-            0,
-            Entity::Npc((NpcFaction::Guards, Ship::new(statics, fitting))),
-        );
-    }
+#[test]
+fn quarantine_keeps_entities_out_of_the_game() {
+    let backend = Arc::new(super::Memory::default());
+    let mut sites = Sites::new(backend.clone());
+    ensure_static_sites(&Statics::default(), &SpawnTables::default(), &mut sites).unwrap();
+    let solarsystem = Solarsystem::Vosu;
+    let site = Site::AsteroidField(42);
+    sites
+        .add_site(solarsystem, 1, site, &[Entity::Facility(Facility::Station)])
+        .unwrap();
+    sites
+        .quarantine_site(QuarantinedSite {
+            solarsystem,
+            site,
+            tick: 3,
+            reason: "broken".to_string(),
+        })
+        .unwrap();
+    assert!(!sites.read_sites(solarsystem).unwrap().all().contains(&site));
+    assert!(sites.read_entities(solarsystem, site).is_err());
+    assert!(backend
+        .read(&filename_quarantine_entities(solarsystem, site))
+        .unwrap()
+        .is_some());
+    assert_eq!(sites.read_quarantined().len(), 1);
+}
+
+#[test]
+fn warping_progress() {
+    let warping = Warping {
+        towards: Site::Station(0),
+        entity: Entity::Facility(Facility::Station),
+        departure: 10,
+        arrival: 14,
+    };
+    assert!(warping.progress(10).abs() < f32::EPSILON);
+    assert!((warping.progress(12) - 0.5).abs() < f32::EPSILON);
+    assert!((warping.progress(20) - 1.0).abs() < f32::EPSILON);
 }
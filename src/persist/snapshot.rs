@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use super::Backend;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+pub struct Settings {
+    pub folder: PathBuf,
+    pub interval: Duration,
+    pub retention: Retention,
+}
+
+/// Which snapshots are kept when a new one is created.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// The most recent snapshots
+    pub keep_latest: usize,
+    /// The most recent snapshot of each of the last days
+    pub keep_daily: usize,
+}
+
+fn filename(timestamp: u64) -> String {
+    format!("persist-{}.tar.gz", timestamp)
+}
+
+fn parse_filename(file: &Path) -> Option<u64> {
+    file.file_name()?
+        .to_str()?
+        .strip_prefix("persist-")?
+        .strip_suffix(".tar.gz")?
+        .parse()
+        .ok()
+}
+
+/// Write every key of the backend into a compressed tar archive within the folder.
+/// Pending changes of a caching backend should be flushed before.
+///
+/// Returns the created archive.
+pub fn create<B: Backend + ?Sized>(backend: &B, folder: &Path) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let file = folder.join(filename(timestamp));
+    let temp = folder.join(format!("{}.tmp", filename(timestamp)));
+    fs::create_dir_all(folder)?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(
+        fs::File::create(&temp)?,
+        Compression::default(),
+    ));
+    for key in backend.list("") {
+        if let Some(content) = backend.read(&key)? {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(timestamp);
+            header.set_cksum();
+            builder.append_data(&mut header, &key, content.as_bytes())?;
+        }
+    }
+    builder.into_inner()?.finish()?.sync_all()?;
+    fs::rename(&temp, &file)?;
+    Ok(file)
+}
+
+/// Replace everything within the backend with the content of the snapshot.
+///
+/// Returns the amount of restored keys.
+pub fn restore<B: Backend + ?Sized>(backend: &B, file: &Path) -> Result<usize> {
+    let mut archive =
+        tar::Archive::new(GzDecoder::new(fs::File::open(file).map_err(|err| {
+            anyhow::anyhow!("failed to open snapshot {:?} {}", file, err)
+        })?));
+
+    let mut restore = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let key = entry
+            .path()?
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("snapshot contains non utf8 path"))?
+            .to_string();
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        restore.push((key, content));
+    }
+
+    backend.begin()?;
+    let result = (|| {
+        for key in backend.list("") {
+            backend.delete(&key)?;
+        }
+        for (key, content) in &restore {
+            backend.write(key, content)?;
+        }
+        Ok(())
+    })();
+    if let Err(err) = result {
+        backend.rollback()?;
+        return Err(err);
+    }
+    backend.commit()?;
+    backend.flush()?;
+    Ok(restore.len())
+}
+
+/// Delete the snapshots within the folder which are not kept by the retention.
+///
+/// Returns the deleted snapshots.
+pub fn prune(folder: &Path, retention: Retention) -> Result<Vec<PathBuf>> {
+    let existing = fs::read_dir(folder)?
+        .filter_map(std::result::Result::ok)
+        .filter_map(|o| parse_filename(&o.path()))
+        .collect::<Vec<_>>();
+    let mut deleted = Vec::new();
+    for timestamp in outdated(existing, retention) {
+        let file = folder.join(filename(timestamp));
+        fs::remove_file(&file)?;
+        deleted.push(file);
+    }
+    Ok(deleted)
+}
+
+fn outdated(mut timestamps: Vec<u64>, retention: Retention) -> Vec<u64> {
+    timestamps.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut keep = timestamps
+        .iter()
+        .take(retention.keep_latest)
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    let mut days = BTreeSet::new();
+    for timestamp in &timestamps {
+        if days.len() >= retention.keep_daily {
+            break;
+        }
+        if days.insert(timestamp / SECONDS_PER_DAY) {
+            keep.insert(*timestamp);
+        }
+    }
+
+    timestamps
+        .into_iter()
+        .filter(|o| !keep.contains(o))
+        .collect()
+}
+
+#[test]
+fn outdated_keeps_latest_and_daily() {
+    let day = SECONDS_PER_DAY;
+    let timestamps = vec![
+        day * 10 + 100,
+        day * 10 + 200,
+        day * 10 + 300,
+        day * 9 + 100,
+        day * 9 + 200,
+        day * 7 + 100,
+        day * 5 + 100,
+    ];
+    let retention = Retention {
+        keep_latest: 2,
+        keep_daily: 3,
+    };
+    let mut result = outdated(timestamps, retention);
+    result.sort_unstable();
+    assert_eq!(result, vec![day * 5 + 100, day * 9 + 100, day * 10 + 100]);
+}
+
+#[test]
+fn create_and_restore() {
+    use super::Memory;
+    let folder = std::env::temp_dir().join(format!(
+        "space-game-backend-test-snapshot-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&folder);
+
+    let backend = Memory::default();
+    backend.write("market/Aromit.yaml", "a").unwrap();
+    backend
+        .write("sites/entities/Vosu/Station0.yaml", "b")
+        .unwrap();
+    let file = create(&backend, &folder).unwrap();
+    assert!(parse_filename(&file).is_some());
+
+    backend.write("market/Aromit.yaml", "changed").unwrap();
+    backend.write("market/Solmit.yaml", "new").unwrap();
+    assert_eq!(restore(&backend, &file).unwrap(), 2);
+    assert_eq!(
+        backend.list(""),
+        vec!["market/Aromit.yaml", "sites/entities/Vosu/Station0.yaml"]
+    );
+    assert_eq!(
+        backend.read("market/Aromit.yaml").unwrap().as_deref(),
+        Some("a")
+    );
+
+    fs::remove_dir_all(folder).unwrap();
+}
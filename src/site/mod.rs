@@ -0,0 +1,145 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use space_game_typings::fixed::Statics;
+use space_game_typings::ship::Ship;
+use space_game_typings::site::instruction::Instruction as TypingsInstruction;
+use space_game_typings::site::SiteLogActor;
+use space_game_typings::storage::Storage;
+
+use crate::persist::site::Wreck;
+
+/// Ticks a wreck stays in its site before it vanishes.
+pub const WRECK_EXPIRE_TICKS: u64 = 30;
+
+/// Site instructions of the typings extended with the ones only the backend knows.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Instruction {
+    Typings(TypingsInstruction),
+    Backend(BackendInstruction),
+    Npc(NpcInstruction),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendInstruction {
+    /// Move as much as fits from the wreck of the site into the own cargo
+    Loot { wreck_index: u8 },
+}
+
+/// Instructions players can not send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NpcInstruction {
+    /// Another ship of the own faction warps in
+    CallReinforcements,
+}
+
+impl From<TypingsInstruction> for Instruction {
+    fn from(instruction: TypingsInstruction) -> Self {
+        Self::Typings(instruction)
+    }
+}
+
+/// Only the instructions the typings know about.
+pub fn typings(instructions: &[Instruction]) -> Vec<TypingsInstruction> {
+    instructions
+        .iter()
+        .filter_map(|o| match o {
+            Instruction::Typings(instruction) => Some(*instruction),
+            Instruction::Backend(_) | Instruction::Npc(_) => None,
+        })
+        .collect()
+}
+
+/// Drop a random part of the cargo and the fitted modules of a destroyed ship.
+/// Returns `None` when nothing survived.
+pub fn wreck(rng: &mut impl Rng, actor: SiteLogActor, ship: &Ship, expires: u64) -> Option<Wreck> {
+    let mut wreck = Wreck {
+        actor,
+        storage: Storage::new_empty(),
+        expires,
+    };
+    for (item, amount) in ship.cargo.to_vec() {
+        let dropped = rng.gen_range(0..=amount);
+        if dropped > 0 {
+            wreck.storage.saturating_add(item, dropped);
+        }
+    }
+    let fitting = &ship.fitting;
+    for module in &fitting.slots_passive {
+        if rng.gen_bool(0.5) {
+            wreck.storage.saturating_add(*module, 1);
+        }
+    }
+    for module in &fitting.slots_targeted {
+        if rng.gen_bool(0.5) {
+            wreck.storage.saturating_add(*module, 1);
+        }
+    }
+    for module in &fitting.slots_untargeted {
+        if rng.gen_bool(0.5) {
+            wreck.storage.saturating_add(*module, 1);
+        }
+    }
+    if wreck.storage.to_vec().is_empty() {
+        None
+    } else {
+        Some(wreck)
+    }
+}
+
+/// Move as much of the wreck as fits into the cargo of the ship.
+pub fn loot(statics: &Statics, ship: &mut Ship, wreck: &mut Wreck) {
+    for (item, amount) in wreck.storage.to_vec() {
+        let free = ship.free_cargo(statics);
+        let amount = wreck.storage.take_max(item, free.min(amount));
+        ship.cargo.saturating_add(item, amount);
+    }
+}
+
+#[test]
+fn wreck_never_holds_more_than_the_ship() {
+    use rand::SeedableRng;
+    use space_game_typings::fixed::item::Ore;
+    use space_game_typings::fixed::module::Targeted;
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    use space_game_typings::ship::Fitting;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let ship = Ship {
+        fitting: Fitting {
+            slots_targeted: vec![Targeted::RookieLaser],
+            ..Fitting::default()
+        },
+        cargo: Storage::new_single(Ore::Aromit, 10),
+        ..Ship::default()
+    };
+    for _ in 0..20 {
+        if let Some(wreck) = wreck(&mut rng, SiteLogActor::Npc(NpcFaction::Pirates), &ship, 5) {
+            for (item, amount) in wreck.storage.to_vec() {
+                if item == Ore::Aromit.into() {
+                    assert!(amount <= 10);
+                } else {
+                    assert_eq!(item, Targeted::RookieLaser.into());
+                    assert_eq!(amount, 1);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn loot_moves_into_cargo() {
+    use space_game_typings::fixed::item::Ore;
+    use space_game_typings::fixed::npc_faction::NpcFaction;
+    let statics = Statics::default();
+    let mut ship = Ship::default();
+    let mut wreck = Wreck {
+        actor: SiteLogActor::Npc(NpcFaction::Pirates),
+        storage: Storage::new_single(Ore::Aromit, 2),
+        expires: 5,
+    };
+    let free = ship.free_cargo(&statics);
+    loot(&statics, &mut ship, &mut wreck);
+    let looted = free.min(2);
+    assert_eq!(ship.cargo.take_max(Ore::Aromit, 2), looted);
+    assert_eq!(wreck.storage.take_max(Ore::Aromit, 2), 2 - looted);
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use space_game_typings::fixed::item::Ore;
+use space_game_typings::fixed::npc_faction::NpcFaction;
+use space_game_typings::fixed::solarsystem::Solarsystem;
+use space_game_typings::fixed::Statics;
+use space_game_typings::ship::{Fitting, Ship};
+use space_game_typings::site::Entity;
+
+/// Used when the setting `spawn-tables` is unset.
+const BUILT_IN: &str = include_str!("../spawn-tables.yaml");
+
+/// Which sites spawn in a solarsystem and what they contain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpawnTables {
+    /// A solarsystem uses the one with the highest `min-security` it reaches
+    pub security: Vec<SpawnTable>,
+    /// Replace the table by security of single solarsystems
+    #[serde(default)]
+    pub solarsystems: HashMap<Solarsystem, SpawnTable>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpawnTable {
+    /// Ignored for the tables of single solarsystems
+    #[serde(default)]
+    pub min_security: u8,
+    pub asteroid_fields: AsteroidFields,
+    pub pirates: Pirates,
+    /// Guarding every station and stargate
+    pub guards: Npcs,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AsteroidFields {
+    /// Asteroid fields kept in the solarsystem
+    pub amount: u8,
+    /// Asteroids a new asteroid field starts with
+    pub asteroids: Between,
+    /// Size of a new asteroid relative to the `amount` and `structure` of its ore
+    pub size_percent: Between,
+    pub ores: Vec<OreSpawn>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OreSpawn {
+    pub ore: Ore,
+    /// How likely an asteroid is of this ore compared to the other ores
+    pub weight: u32,
+    pub amount: u16,
+    pub structure: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Pirates {
+    /// Pirates appear in an asteroid field without NPCs once in this many ticks
+    pub chance: u32,
+    /// Used instead of `chance` while players stay after clearing a wave
+    pub next_wave_chance: u32,
+    /// Each wave cleared by players brings the next one. The last one repeats.
+    pub waves: Vec<Npcs>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Npcs {
+    pub ships: u8,
+    pub fitting: Fitting,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Between {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Default for SpawnTables {
+    fn default() -> Self {
+        serde_yaml::from_str(BUILT_IN).expect("built-in spawn tables should be readable")
+    }
+}
+
+impl SpawnTables {
+    /// Read the spawn tables from the file or use the built-in ones.
+    pub fn load(file: Option<&Path>) -> Result<Self> {
+        if let Some(file) = file {
+            let content = std::fs::read_to_string(file)
+                .map_err(|err| anyhow!("failed to read spawn tables {:?} {}", file, err))?;
+            serde_yaml::from_str(&content)
+                .map_err(|err| anyhow!("failed to parse spawn tables {:?} {}", file, err))
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Ensure every solarsystem has a table which only spawns valid ships.
+    pub fn validate(&self, statics: &Statics) -> Result<()> {
+        if !self.security.iter().any(|o| o.min_security == 0) {
+            return Err(anyhow!("spawn tables need a table with min-security 0"));
+        }
+        for table in &self.security {
+            table.validate(statics).map_err(|err| {
+                anyhow!("spawn table min-security {} {}", table.min_security, err)
+            })?;
+        }
+        for (solarsystem, table) in &self.solarsystems {
+            if !statics.solarsystems.data.contains_key(solarsystem) {
+                return Err(anyhow!(
+                    "spawn table of unknown solarsystem {:?}",
+                    solarsystem
+                ));
+            }
+            table
+                .validate(statics)
+                .map_err(|err| anyhow!("spawn table {:?} {}", solarsystem, err))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, statics: &Statics, solarsystem: Solarsystem) -> &SpawnTable {
+        self.solarsystems
+            .get(&solarsystem)
+            .unwrap_or_else(|| self.by_security(statics.solarsystems.get(&solarsystem).security))
+    }
+
+    fn by_security(&self, security: u8) -> &SpawnTable {
+        self.security
+            .iter()
+            .filter(|o| o.min_security <= security)
+            .max_by_key(|o| o.min_security)
+            .expect("validated to have a table for min-security 0")
+    }
+}
+
+impl SpawnTable {
+    fn validate(&self, statics: &Statics) -> Result<()> {
+        let fields = &self.asteroid_fields;
+        fields.asteroids.validate("asteroids")?;
+        fields.size_percent.validate("size-percent")?;
+        if fields.size_percent.min == 0 {
+            return Err(anyhow!("size-percent has to be at least 1"));
+        }
+        if fields.amount > 0 && fields.asteroids.min == 0 {
+            return Err(anyhow!("asteroid fields need at least 1 asteroid"));
+        }
+        if fields.amount > 0 && fields.ores.iter().map(|o| o.weight).sum::<u32>() == 0 {
+            return Err(anyhow!("asteroid fields need an ore with a weight"));
+        }
+        if let Some(ore) = fields
+            .ores
+            .iter()
+            .find(|o| o.amount == 0 || o.structure == 0)
+        {
+            return Err(anyhow!("asteroids of {:?} would be empty", ore.ore));
+        }
+        if self.pirates.chance == 0 || self.pirates.next_wave_chance == 0 {
+            return Err(anyhow!("pirate chances have to be at least 1"));
+        }
+        if self.pirates.waves.is_empty() {
+            return Err(anyhow!("pirates need at least one wave"));
+        }
+        for (wave, npcs) in self.pirates.waves.iter().enumerate() {
+            npcs.fitting
+                .is_valid(statics)
+                .map_err(|err| anyhow!("pirate wave {} {}", wave, err))?;
+        }
+        self.guards
+            .fitting
+            .is_valid(statics)
+            .map_err(|err| anyhow!("guards {}", err))
+    }
+
+    pub fn belt_asteroids(&self, rng: &mut impl Rng) -> Vec<Entity> {
+        let fields = &self.asteroid_fields;
+        let total: u32 = fields.ores.iter().map(|o| o.weight).sum();
+        if total == 0 {
+            return Vec::new();
+        }
+        let amount = rng.gen_range(fields.asteroids.min..=fields.asteroids.max);
+        let mut asteroids = Vec::new();
+        for _ in 0..amount {
+            let mut roll = rng.gen_range(0..total);
+            for spawn in &fields.ores {
+                if roll < spawn.weight {
+                    let percent = rng.gen_range(fields.size_percent.min..=fields.size_percent.max);
+                    asteroids.push(spawn.asteroid(percent));
+                    break;
+                }
+                roll -= spawn.weight;
+            }
+        }
+        asteroids
+    }
+
+    /// The biggest asteroid of the ore a new asteroid field can start with.
+    /// Partly mined asteroids regrow up to it.
+    pub fn asteroid_cap(&self, ore: Ore) -> Option<Entity> {
+        let percent = self.asteroid_fields.size_percent.max;
+        self.asteroid_fields
+            .ores
+            .iter()
+            .filter(|o| o.ore == ore)
+            .max_by_key(|o| (o.amount, o.structure))
+            .map(|o| o.asteroid(percent))
+    }
+
+    pub fn pirate_wave(&self, statics: &Statics, wave: u8) -> Vec<Entity> {
+        let last = self.pirates.waves.len().saturating_sub(1);
+        self.pirates
+            .waves
+            .get(usize::from(wave).min(last))
+            .map(|npcs| npcs.spawn(statics, NpcFaction::Pirates))
+            .unwrap_or_default()
+    }
+
+    pub fn guards(&self, statics: &Statics) -> Vec<Entity> {
+        self.guards.spawn(statics, NpcFaction::Guards)
+    }
+}
+
+impl OreSpawn {
+    fn asteroid(self, percent: u16) -> Entity {
+        let scale = |value: u16| {
+            let scaled = u32::from(value) * u32::from(percent) / 100;
+            u16::try_from(scaled).unwrap_or(u16::MAX).max(1)
+        };
+        Entity::new_asteroid(self.ore, scale(self.amount), scale(self.structure))
+    }
+}
+
+impl Npcs {
+    fn spawn(&self, statics: &Statics, faction: NpcFaction) -> Vec<Entity> {
+        (0..self.ships)
+            .map(|_| Entity::Npc((faction, Ship::new(statics, self.fitting.clone()))))
+            .collect()
+    }
+}
+
+impl Between {
+    fn validate(self, name: &str) -> Result<()> {
+        if self.min > self.max {
+            Err(anyhow!(
+                "{} min {} is above max {}",
+                name,
+                self.min,
+                self.max
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn built_in_tables_by_security() {
+    let tables = SpawnTables::default();
+    assert_eq!(tables.by_security(0).min_security, 0);
+    assert_eq!(tables.by_security(50).min_security, 34);
+    assert_eq!(tables.by_security(100).min_security, 67);
+    let rare = |security| {
+        tables
+            .by_security(security)
+            .asteroid_fields
+            .ores
+            .iter()
+            .any(|o| o.ore == Ore::Vesmit)
+    };
+    assert!(rare(0));
+    assert!(!rare(100));
+}
+
+#[test]
+fn built_in_tables_are_valid() {
+    SpawnTables::default()
+        .validate(&Statics::default())
+        .unwrap();
+}
+
+#[test]
+fn tables_need_min_security_zero() {
+    let mut tables = SpawnTables::default();
+    tables.security.retain(|o| o.min_security > 0);
+    assert!(tables.validate(&Statics::default()).is_err());
+}
+
+#[test]
+fn asteroid_fields_need_asteroids() {
+    let mut tables = SpawnTables::default();
+    for table in &mut tables.security {
+        table.asteroid_fields.asteroids.min = 0;
+    }
+    assert!(tables.validate(&Statics::default()).is_err());
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let content = BUILT_IN.replace("next-wave-chance", "next-wave-chances");
+    assert!(serde_yaml::from_str::<SpawnTables>(&content).is_err());
+}
+
+#[test]
+fn tables_survive_the_journal() {
+    let tables = SpawnTables::default();
+    let json = serde_json::to_string(&tables).unwrap();
+    let read: SpawnTables = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&read).unwrap(), json);
+}
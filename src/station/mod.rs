@@ -1,15 +1,39 @@
+use serde::{Deserialize, Serialize};
 use space_game_typings::fixed::item::Item;
 use space_game_typings::fixed::module::Module;
 use space_game_typings::fixed::solarsystem::Solarsystem;
 use space_game_typings::fixed::Statics;
-use space_game_typings::player::location::{PlayerLocation, PlayerLocationSite};
+use space_game_typings::player::location::{PlayerLocation, PlayerLocationStation};
 use space_game_typings::player::Player;
-use space_game_typings::ship::Ship;
+use space_game_typings::ship::{Fitting, Ship};
 use space_game_typings::site::{Entity, Site};
-use space_game_typings::station::instruction::Instruction;
+use space_game_typings::station::instruction::Instruction as TypingsInstruction;
 use space_game_typings::storage::Storage;
 
-use crate::persist::Persist;
+use crate::config::{log_enabled, LogLevel};
+use crate::persist::journal::Event;
+use crate::persist::{InsurancePolicy, InsuredShip, Persist};
+
+/// Station instructions of the typings extended with the ones only the backend knows.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Instruction {
+    Typings(TypingsInstruction),
+    Backend(BackendInstruction),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendInstruction {
+    /// Respawn in the current station when dying
+    SetHome,
+    /// Insure the current ship for a premium of its payout
+    Insure,
+}
+
+/// Paperclips paid out for every mineral the insured modules recycle into.
+const INSURANCE_PAPERCLIPS_PER_MINERAL: u64 = 10;
+/// Share of the payout which has to be paid up front.
+const INSURANCE_PREMIUM_DIVISOR: u64 = 5;
 
 pub fn do_instructions(
     statics: &Statics,
@@ -25,10 +49,40 @@ pub fn do_instructions(
             return Err(anyhow::anyhow!("player is not docked"))
         }
     };
-    for instruction in instructions.iter().copied() {
-        do_instruction(statics, persist, player, instruction, solarsystem, station)?;
+    let undocking = persist
+        .sites
+        .read_entity_undocking(solarsystem, station)
+        .iter()
+        .any(|o| matches!(o, Entity::Player((p, _)) if p == &player));
+    if undocking {
+        return Err(anyhow::anyhow!("player is already undocking"));
     }
-    Ok(())
+    persist.transaction(|persist| {
+        for instruction in instructions.iter().copied() {
+            match instruction {
+                Instruction::Typings(instruction) => {
+                    do_instruction(statics, persist, player, instruction, solarsystem, station)?;
+                }
+                Instruction::Backend(BackendInstruction::SetHome) => {
+                    persist.player_generals.write_home(
+                        player,
+                        Some(PlayerLocationStation {
+                            solarsystem,
+                            station,
+                        }),
+                    )?;
+                }
+                Instruction::Backend(BackendInstruction::Insure) => {
+                    insure(statics, persist, player, solarsystem, station)?;
+                }
+            }
+        }
+        persist.journal.record(Event::StationInstructions {
+            player,
+            instructions: instructions.to_vec(),
+        });
+        Ok(())
+    })
 }
 
 #[allow(clippy::too_many_lines)]
@@ -36,7 +90,7 @@ fn do_instruction(
     statics: &Statics,
     persist: &mut Persist,
     player: Player,
-    instruction: Instruction,
+    instruction: TypingsInstruction,
     solarsystem: Solarsystem,
     station: u8,
 ) -> anyhow::Result<()> {
@@ -44,31 +98,50 @@ fn do_instruction(
         .player_station_assets
         .read(player, solarsystem, station);
     match instruction {
-        Instruction::SwitchShip(index) => {
+        TypingsInstruction::SwitchShip(index) => {
+            let before = assets.current_ship.clone();
             assets.switch_ship(index);
+            persist.player_insurances.switch(
+                player,
+                solarsystem,
+                station,
+                before.as_ref(),
+                assets.current_ship.as_ref(),
+            )?;
         }
-        Instruction::Repair => {
+        TypingsInstruction::Repair => {
             if let Some(ship) = &mut assets.current_ship {
                 let collateral = ship.fitting.maximum_collateral(statics);
                 if ship.collateral != collateral {
-                    eprintln!("repair player ship in station {:?}", player);
+                    if log_enabled(LogLevel::Debug) {
+                        eprintln!("repair player ship in station {:?}", player);
+                    }
                     ship.collateral = collateral;
                 }
             }
         }
-        Instruction::Undock => {
-            // TODO: undocking shouldnt be instantanious. It should also be handled with the round logic
+        TypingsInstruction::Undock => {
+            // Quarantined stations are no site anymore and would never run the undock
+            let open = persist
+                .sites
+                .read_sites(solarsystem)?
+                .all()
+                .contains(&Site::Station(station));
+            if !open {
+                return Err(anyhow::anyhow!("station is closed for undocking"));
+            }
+            // The ship appears in the station site with the next round
             let ship = assets.current_ship.take().unwrap_or_default();
-            let site = Site::Station(station);
-            let mut entities = persist.sites.read_entities(solarsystem, site)?;
-            entities.push(Entity::Player((player, ship)));
-            persist.sites.write_entities(solarsystem, site, &entities)?;
-            persist.player_locations.write(
-                player,
-                PlayerLocation::Site(PlayerLocationSite { solarsystem, site }),
+            persist
+                .player_insurances
+                .undock(player, solarsystem, station)?;
+            persist.sites.add_entity_undocking(
+                solarsystem,
+                station,
+                Entity::Player((player, ship)),
             )?;
         }
-        Instruction::ModuleAdd(module) => {
+        TypingsInstruction::ModuleAdd(module) => {
             let mut ship = assets.current_ship.clone().unwrap_or_default();
             match module {
                 Module::Passive(m) => ship.fitting.slots_passive.push(m),
@@ -80,22 +153,22 @@ fn do_instruction(
                 assets.current_ship = Some(ship);
             }
         }
-        Instruction::ModulePassiveRemove(index) => {
+        TypingsInstruction::ModulePassiveRemove(index) => {
             let mut ship = assets.current_ship.unwrap_or_default();
             ship_module_remove(&mut assets.storage, &mut ship.fitting.slots_passive, index);
             assets.current_ship = Some(ship);
         }
-        Instruction::ModuleTargetedRemove(index) => {
+        TypingsInstruction::ModuleTargetedRemove(index) => {
             let mut ship = assets.current_ship.unwrap_or_default();
             ship_module_remove(&mut assets.storage, &mut ship.fitting.slots_targeted, index);
             assets.current_ship = Some(ship);
         }
-        Instruction::ModuleUntargetedRemove(i) => {
+        TypingsInstruction::ModuleUntargetedRemove(i) => {
             let mut ship = assets.current_ship.unwrap_or_default();
             ship_module_remove(&mut assets.storage, &mut ship.fitting.slots_untargeted, i);
             assets.current_ship = Some(ship);
         }
-        Instruction::ShipCargoLoad(i) => {
+        TypingsInstruction::ShipCargoLoad(i) => {
             if assets.current_ship.is_none() {
                 assets.current_ship = Some(Ship::default());
             }
@@ -105,13 +178,13 @@ fn do_instruction(
             let amount = assets.storage.take_max(i.item, amount);
             ship.cargo.saturating_add(i.item, amount);
         }
-        Instruction::ShipCargoUnload(i) => {
+        TypingsInstruction::ShipCargoUnload(i) => {
             if let Some(ship) = &mut assets.current_ship {
                 let amount = ship.cargo.take_max(i.item, i.amount);
                 assets.storage.saturating_add(i.item, amount);
             }
         }
-        Instruction::Buy(o) => {
+        TypingsInstruction::Buy(o) => {
             let (item, order) = o.to_order(player, solarsystem, station);
             let mut general = persist.player_generals.read(player);
             if let Some(remaining) = general.paperclips.checked_sub(order.total_paperclips()) {
@@ -122,7 +195,7 @@ fn do_instruction(
                 return Err(anyhow::anyhow!("not enough money for buy order"));
             }
         }
-        Instruction::Sell(o) => {
+        TypingsInstruction::Sell(o) => {
             let (item, order) = o.to_order(player, solarsystem, station);
             if assets.storage.take_exact(item, order.amount) {
                 persist.market.sell(item, order)?;
@@ -130,7 +203,7 @@ fn do_instruction(
                 return Err(anyhow::anyhow!("not enough items for sell order"));
             }
         }
-        Instruction::Recycle { item, amount } => {
+        TypingsInstruction::Recycle { item, amount } => {
             recycle(statics, &mut assets.storage, item, amount);
         }
     }
@@ -146,6 +219,75 @@ fn do_instruction(
     Ok(())
 }
 
+fn insure(
+    statics: &Statics,
+    persist: &mut Persist,
+    player: Player,
+    solarsystem: Solarsystem,
+    station: u8,
+) -> anyhow::Result<()> {
+    let fitting = persist
+        .player_station_assets
+        .read(player, solarsystem, station)
+        .current_ship
+        .ok_or_else(|| anyhow::anyhow!("no ship to insure"))?
+        .fitting;
+    if persist
+        .player_insurances
+        .is_current_insured(player, solarsystem, station)
+    {
+        return Err(anyhow::anyhow!("ship is already insured"));
+    }
+    let payout = insurance_payout(statics, &fitting);
+    if payout == 0 {
+        return Err(anyhow::anyhow!("ship has nothing insurable fitted"));
+    }
+    let premium = payout / INSURANCE_PREMIUM_DIVISOR;
+    let mut general = persist.player_generals.read(player);
+    general.paperclips = general
+        .paperclips
+        .checked_sub(premium)
+        .ok_or_else(|| anyhow::anyhow!("not enough money for insurance premium"))?;
+    persist.player_generals.write(player, &general)?;
+    persist.player_insurances.add(
+        player,
+        InsurancePolicy {
+            ship: InsuredShip::Current {
+                solarsystem,
+                station,
+            },
+            payout,
+        },
+    )
+}
+
+/// The ship layout is not an item so only the fitted modules count.
+/// Modules of the free rookie ship are not worth anything.
+pub fn insurance_payout(statics: &Statics, fitting: &Fitting) -> u64 {
+    let mut items = fitted_items(fitting);
+    for free in fitted_items(&Ship::default().fitting) {
+        if let Some(index) = items.iter().position(|o| o == &free) {
+            items.remove(index);
+        }
+    }
+    let minerals: u64 = items
+        .iter()
+        .flat_map(|item| statics.items.get(item).recycle.values())
+        .map(|amount| u64::from(*amount))
+        .sum();
+    minerals.saturating_mul(INSURANCE_PAPERCLIPS_PER_MINERAL)
+}
+
+fn fitted_items(fitting: &Fitting) -> Vec<Item> {
+    fitting
+        .slots_passive
+        .iter()
+        .map(|o| Item::from(*o))
+        .chain(fitting.slots_targeted.iter().map(|o| Item::from(*o)))
+        .chain(fitting.slots_untargeted.iter().map(|o| Item::from(*o)))
+        .collect()
+}
+
 fn ship_module_remove<T: Into<Item>>(storage: &mut Storage, slots: &mut Vec<T>, index: u8) {
     let index = index as usize;
     if index < slots.len() {
@@ -182,3 +324,51 @@ fn recycle_something() {
     recycle(&statics, &mut storage, Passive::RookieArmorPlate, 2);
     assert_eq!(storage.to_vec(), expected.to_vec());
 }
+
+#[test]
+fn rookie_ship_is_not_insurable() {
+    let statics = Statics::default();
+    assert_eq!(insurance_payout(&statics, &Ship::default().fitting), 0);
+}
+
+#[test]
+fn quarantined_stations_refuse_undocks() {
+    use crate::persist::site::QuarantinedSite;
+    let statics = Statics::default();
+    let backend: std::sync::Arc<dyn crate::persist::Backend> =
+        std::sync::Arc::new(crate::persist::Memory::default());
+    let mut persist = Persist::new(backend);
+    crate::persist::ensure_static_sites(
+        &statics,
+        &crate::spawn_tables::SpawnTables::default(),
+        &mut persist.sites,
+    )
+    .unwrap();
+    let solarsystem = Solarsystem::Vosu;
+    let player = Player::Telegram(42);
+    persist
+        .player_locations
+        .write(
+            player,
+            PlayerLocation::Station(PlayerLocationStation {
+                solarsystem,
+                station: 0,
+            }),
+        )
+        .unwrap();
+    persist
+        .sites
+        .quarantine_site(QuarantinedSite {
+            solarsystem,
+            site: Site::Station(0),
+            tick: 3,
+            reason: "broken".to_string(),
+        })
+        .unwrap();
+    let undock = [Instruction::Typings(TypingsInstruction::Undock)];
+    assert!(do_instructions(&statics, &mut persist, player, &undock).is_err());
+    assert!(persist
+        .sites
+        .read_entity_undocking(solarsystem, 0)
+        .is_empty());
+}
@@ -2,43 +2,89 @@
 
 use std::sync::Arc;
 
-use async_std::sync::{Mutex, MutexGuardArc};
+use async_std::sync::{Mutex, MutexGuardArc, RwLock, RwLockReadGuard};
 use space_game_typings::fixed::Statics;
-use space_game_typings::player::location::PlayerLocation;
-use space_game_typings::player::Player;
-use space_game_typings::site::instruction::Instruction as SiteInstruction;
+use space_game_typings::player::location::{PlayerLocation, PlayerLocationStation};
+use space_game_typings::player::{General, Player};
 use space_game_typings::site::Entity;
-use space_game_typings::station::instruction::Instruction as StationInstruction;
 use tide::http::mime;
 use tide::utils::After;
 use tide::{Request, Response, StatusCode};
 
-use crate::persist::site::read_entitiy_warping;
+use crate::config::{log_enabled, LogLevel};
+use crate::gameloop;
 use crate::persist::Persist;
-use crate::station;
+use crate::site::Instruction as SiteInstruction;
+use crate::spawn_tables::SpawnTables;
+use crate::station::{self, Instruction as StationInstruction};
 
 mod site_entity;
 
 #[derive(Clone)]
 pub struct State {
     pub statics: Arc<Statics>,
+    pub spawn_tables: Arc<SpawnTables>,
     pub persist: Arc<Mutex<Persist>>,
+    /// Read only view of the persist which does not wait for the gameloop
+    pub view: Arc<RwLock<Persist>>,
+    pub gameloop: Arc<gameloop::Control>,
+    /// Bearer token of the `/admin` routes. They are refused when unset.
+    pub admin_token: Option<Arc<str>>,
 }
 
 impl State {
     pub async fn persist(&self) -> MutexGuardArc<Persist> {
         self.persist.lock_arc().await
     }
+
+    pub async fn view(&self) -> RwLockReadGuard<'_, Persist> {
+        self.view.read().await
+    }
+
+    fn require_admin(&self, req: &Request<Self>) -> tide::Result<()> {
+        let expected = self.admin_token.as_ref().ok_or_else(|| {
+            tide::Error::from_str(StatusCode::Forbidden, "admin routes are disabled")
+        })?;
+        let given = req
+            .header("Authorization")
+            .and_then(|o| o.last().as_str().strip_prefix("Bearer "));
+        if given == Some(&**expected) {
+            Ok(())
+        } else {
+            Err(tide::Error::from_str(
+                StatusCode::Unauthorized,
+                "admin token missing or wrong",
+            ))
+        }
+    }
+
+    /// Change the persist and publish all the changes to the view at once.
+    pub async fn change<T, F>(&self, change: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Persist) -> anyhow::Result<T>,
+    {
+        let mut persist = self.persist().await;
+        persist.stage()?;
+        let result = change(&mut persist);
+        {
+            let _view = self.view.write().await;
+            persist.publish();
+        }
+        let result = result?;
+        persist.flush()?;
+        Ok(result)
+    }
 }
 
 pub fn init(state: State) -> tide::Server<State> {
     let mut app = tide::with_state(state);
 
-    #[cfg(debug_assertions)]
     app.with(tide::utils::Before(|request: Request<_>| async {
-        let method = request.method();
-        let path = request.url().path();
-        println!("HTTP-REQUEST {} {}", method, path);
+        if log_enabled(LogLevel::Debug) {
+            let method = request.method();
+            let path = request.url().path();
+            println!("HTTP-REQUEST {} {}", method, path);
+        }
         request
     }));
 
@@ -58,7 +104,9 @@ pub fn init(state: State) -> tide::Server<State> {
             .build())
     });
 
+    app.at("/player/:player/flags").get(player_flags);
     app.at("/player/:player/generals").get(player_generals);
+    app.at("/player/:player/insurances").get(player_insurances);
     app.at("/player/:player/location").get(player_location);
     app.at("/player/:player/ship").get(player_ship);
     app.at("/player/:player/station-assets/:solarsystem/:station")
@@ -76,9 +124,22 @@ pub fn init(state: State) -> tide::Server<State> {
 
     app.at("/sites/:solarsystem").get(sites);
     app.at("/sites/:solarsystem/:unique").get(site_entities);
+    app.at("/sites/:solarsystem/:unique/wrecks")
+        .get(site_wrecks);
 
     app.at("/market/:item").get(get_market);
 
+    app.at("/time").get(get_time);
+
+    app.at("/admin/gameloop").get(get_gameloop);
+    app.at("/admin/quarantined-sites")
+        .get(get_quarantined_sites);
+    app.at("/admin/gameloop/pause").post(post_gameloop_pause);
+    app.at("/admin/gameloop/resume").post(post_gameloop_resume);
+    app.at("/admin/gameloop/step").post(post_gameloop_step);
+    app.at("/admin/gameloop/tick-interval")
+        .post(post_gameloop_tick_interval);
+
     app
 }
 
@@ -105,55 +166,123 @@ where
     })
 }
 
+#[derive(serde::Serialize)]
+struct GeneralResponse {
+    #[serde(flatten)]
+    general: General,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home: Option<PlayerLocationStation>,
+    security: i32,
+}
+
 async fn player_generals(req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
-    let body = req.state().persist().await.player_generals.read(player);
+    let view = req.state().view().await;
+    tide_json_response(&GeneralResponse {
+        general: view.player_generals.read(player),
+        home: view.player_generals.read_home(player),
+        security: view
+            .player_generals
+            .read_security(player, view.clock.read()),
+    })
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct LocationResponse {
+    #[serde(flatten)]
+    location: PlayerLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warp_progress: Option<WarpProgress>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct WarpProgress {
+    departure: u64,
+    arrival: u64,
+    /// Share of the way already done between 0 and 1
+    progress: f32,
+}
+
+async fn player_flags(req: Request<State>) -> tide::Result {
+    let player = tide_parse_param(&req, "player")?;
+    let view = req.state().view().await;
+    let tick = view.clock.read();
+    let body = view.player_flags.read(player).active(tick);
     tide_json_response(&body)
 }
 
-async fn player_location(req: Request<State>) -> tide::Result {
+async fn player_insurances(req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
-    let body = req.state().persist().await.player_locations.read(player);
+    let body = req.state().view().await.player_insurances.read(player);
     tide_json_response(&body)
 }
 
+async fn player_location(req: Request<State>) -> tide::Result {
+    let player = tide_parse_param(&req, "player")?;
+    let view = req.state().view().await;
+    let location = view.player_locations.read(player);
+    let warp_progress = if let PlayerLocation::Warp(warp) = &location {
+        let tick = view.clock.read();
+        view.sites
+            .read_entitiy_warping(warp.solarsystem)
+            .iter()
+            .find(|o| matches!(&o.entity, Entity::Player((p, _)) if p == &player))
+            .map(|warping| WarpProgress {
+                departure: warping.departure,
+                arrival: warping.arrival,
+                progress: warping.progress(tick),
+            })
+    } else {
+        None
+    };
+    tide_json_response(&LocationResponse {
+        location,
+        warp_progress,
+    })
+}
+
 async fn player_ship(req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
-    let location = req.state().persist().await.player_locations.read(player);
+    let view = req.state().view().await;
+    let location = view.player_locations.read(player);
     let body = match location {
         PlayerLocation::Site(s) => {
-            let entities = req
-                .state()
-                .persist()
-                .await
-                .sites
-                .read_entities(s.solarsystem, s.site)?;
+            let entities = view.sites.read_entities(s.solarsystem, s.site)?;
             let ship = entities
                 .iter()
                 .find_map(|e| match e {
                     Entity::Player((p, ship)) if p == &player => Some(ship),
                     _ => None,
                 })
-                .expect("player has to be in the site of its location");
+                .ok_or_else(|| {
+                    tide::Error::from_str(
+                        StatusCode::InternalServerError,
+                        "player is not in the site of its location",
+                    )
+                })?;
             ship.clone()
         }
-        PlayerLocation::Station(s) => req
-            .state()
-            .persist()
-            .await
+        PlayerLocation::Station(s) => view
             .player_station_assets
             .read(player, s.solarsystem, s.station)
             .current_ship
             .unwrap_or_default(),
         PlayerLocation::Warp(w) => {
-            let entities = read_entitiy_warping(w.solarsystem);
+            let entities = view.sites.read_entitiy_warping(w.solarsystem);
             let ship = entities
                 .iter()
-                .find_map(|(_site, entity)| match entity {
+                .find_map(|warping| match &warping.entity {
                     Entity::Player((p, ship)) if p == &player => Some(ship),
                     _ => None,
                 })
-                .expect("player has to be in warp to its location");
+                .ok_or_else(|| {
+                    tide::Error::from_str(
+                        StatusCode::InternalServerError,
+                        "player is not in warp to its location",
+                    )
+                })?;
             ship.clone()
         }
     };
@@ -163,16 +292,28 @@ async fn player_ship(req: Request<State>) -> tide::Result {
 async fn site_entities(req: Request<State>) -> tide::Result {
     let solarsystem = tide_parse_param(&req, "solarsystem")?;
     let site = tide_parse_param(&req, "unique")?;
-    let persist = req.state().persist().await;
+    let persist = req.state().view().await;
     let body = site_entity::read(&req.state().statics, &persist, solarsystem, site);
     tide_json_response(&body)
 }
 
+async fn site_wrecks(req: Request<State>) -> tide::Result {
+    let solarsystem = tide_parse_param(&req, "solarsystem")?;
+    let site = tide_parse_param(&req, "unique")?;
+    let body = req
+        .state()
+        .view()
+        .await
+        .sites
+        .read_wrecks(solarsystem, site);
+    tide_json_response(&body)
+}
+
 async fn sites(req: Request<State>) -> tide::Result {
     let solarsystem = tide_parse_param(&req, "solarsystem")?;
     let body = req
         .state()
-        .persist()
+        .view()
         .await
         .sites
         .read_sites(solarsystem)
@@ -186,7 +327,7 @@ async fn station_assets(req: Request<State>) -> tide::Result {
     let station = tide_parse_param(&req, "station")?;
     let body = req
         .state()
-        .persist()
+        .view()
         .await
         .player_station_assets
         .read(player, solarsystem, station);
@@ -197,7 +338,7 @@ async fn get_site_instructions(req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
     let body = req
         .state()
-        .persist()
+        .view()
         .await
         .player_site_instructions
         .read(player);
@@ -207,17 +348,26 @@ async fn get_site_instructions(req: Request<State>) -> tide::Result {
 async fn post_site_instructions(mut req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
     let instructions = req.body_json::<Vec<SiteInstruction>>().await?;
-    println!(
-        "SiteInstructions for player {:?} ({}): {:?}",
-        player,
-        instructions.len(),
-        instructions
-    );
+    if instructions
+        .iter()
+        .any(|o| matches!(o, SiteInstruction::Npc(_)))
+    {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "instruction is only available to NPCs",
+        ));
+    }
+    if log_enabled(LogLevel::Debug) {
+        println!(
+            "SiteInstructions for player {:?} ({}): {:?}",
+            player,
+            instructions.len(),
+            instructions
+        );
+    }
     req.state()
-        .persist()
-        .await
-        .player_site_instructions
-        .add(player, &instructions)?;
+        .change(|persist| persist.player_site_instructions.add(player, &instructions))
+        .await?;
     Ok(Response::builder(StatusCode::Ok).build())
 }
 
@@ -225,21 +375,14 @@ async fn get_player_notifications(req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
     let body = req
         .state()
-        .persist()
-        .await
-        .player_notifications
-        .pop(player)?;
+        .change(|persist| persist.player_notifications.pop(player))
+        .await?;
     tide_json_response(&body)
 }
 
 async fn get_platform_players_with_notifications(req: Request<State>) -> tide::Result {
     let platform = req.param("platform")?;
-    let site_log_players = req
-        .state()
-        .persist()
-        .await
-        .player_notifications
-        .list_players();
+    let site_log_players = req.state().view().await.player_notifications.list_players();
     let body = match platform {
         "telegram" => site_log_players
             .iter()
@@ -258,20 +401,77 @@ async fn get_platform_players_with_notifications(req: Request<State>) -> tide::R
 async fn post_station_instructions(mut req: Request<State>) -> tide::Result {
     let player = tide_parse_param(&req, "player")?;
     let instructions = req.body_json::<Vec<StationInstruction>>().await?;
-    println!(
-        "StationInstructions for player {:?} ({}): {:?}",
-        player,
-        instructions.len(),
-        instructions
-    );
+    if log_enabled(LogLevel::Debug) {
+        println!(
+            "StationInstructions for player {:?} ({}): {:?}",
+            player,
+            instructions.len(),
+            instructions
+        );
+    }
     let statics = &req.state().statics;
-    let persist = &mut req.state().persist().await;
-    station::do_instructions(statics, persist, player, &instructions)?;
+    req.state()
+        .change(|persist| station::do_instructions(statics, persist, player, &instructions))
+        .await?;
     Ok(Response::builder(StatusCode::Ok).build())
 }
 
 async fn get_market(req: Request<State>) -> tide::Result {
     let item = tide_parse_param(&req, "item")?;
-    let body = req.state().persist().await.market.get(item);
+    let body = req.state().view().await.market.get(item);
     tide_json_response(&body)
 }
+
+async fn get_time(req: Request<State>) -> tide::Result {
+    let tick = req.state().view().await.clock.read();
+    tide_json_response(&serde_json::json!({ "tick": tick }))
+}
+
+async fn get_quarantined_sites(req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    let body = req.state().view().await.sites.read_quarantined();
+    tide_json_response(&body)
+}
+
+async fn get_gameloop(req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    tide_json_response(&req.state().gameloop.status())
+}
+
+async fn post_gameloop_pause(req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    req.state().gameloop.set_paused(true);
+    tide_json_response(&req.state().gameloop.status())
+}
+
+async fn post_gameloop_resume(req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    req.state().gameloop.set_paused(false);
+    tide_json_response(&req.state().gameloop.status())
+}
+
+async fn post_gameloop_step(req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    let statics = &req.state().statics;
+    let spawn_tables = &req.state().spawn_tables;
+    let persist = &mut req.state().persist().await;
+    gameloop::step(statics, spawn_tables, persist, &req.state().view).await?;
+    Ok(Response::builder(StatusCode::Ok).build())
+}
+
+/// Body is the amount of seconds between two ticks like `2.5`
+async fn post_gameloop_tick_interval(mut req: Request<State>) -> tide::Result {
+    req.state().require_admin(&req)?;
+    let seconds = req.body_json::<f64>().await?;
+    let interval = std::time::Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|o| !o.is_zero())
+        .ok_or_else(|| {
+            tide::Error::from_str(
+                StatusCode::BadRequest,
+                "tick interval has to be a positive amount of seconds",
+            )
+        })?;
+    req.state().gameloop.set_tick_interval(interval);
+    tide_json_response(&req.state().gameloop.status())
+}